@@ -1,49 +1,7 @@
+use std::time::{SystemTime, self};
 
-use std::{rc::Rc, cell::{Cell, RefCell}, fmt, future, time::{SystemTime, self}};
-
-use crypto::{sha2::{Sha256, Sha224}, digest::Digest, sha3::Sha3};
-use tokio::time::sleep;
-use trie::{node::{hash_node, Node, NilNode}, ID, common::{Hash, self}, Trie};
-
-
-
-// fn displayArr<T:std::fmt::Debug>(arr: &[T]) {
-//     println!("{:?}", arr);
-// }
-
-// fn display_arr2<T:std::fmt::Debug, const N: usize>(arr: [T; N]) {
-//     println!("{:?}", arr);
-// }
-
-async fn eat() {
-    println!("eat");
-    let v = song().await;
-    println!("{}", v);
-}
-
-async fn song() -> String {
-    println!("song");
-    // sleep(std::time::Duration::from_secs(3))
-    sleep(std::time::Duration::from_secs(3)).await;
-    "song over".to_string()
-}
-
-async fn lon() {
-    println!("lon");
-}
-
-async fn do_work() {
-    let w1 = eat();
-    let w3 = lon();
-    futures::join!(w1, w3);
-}
-
-use futures::{executor::block_on};
-
-#[tokio::main]
-async fn main2() {
-    block_on(do_work());
-}
+use crypto::{sha2::Sha256, digest::Digest};
+use trie::{ID, common::Hash, Trie};
 
 fn main() {
     let mut t: Trie = Trie::new(ID::trie_id(Hash::default()));
@@ -57,7 +15,7 @@ fn main() {
         let vs = v.to_le_bytes();
         s256.input(&vs);
         
-        let mut ret = Vec::from_iter(std::iter::repeat(0_u8).take(ret_size));
+        let mut ret = vec![0_u8; ret_size];
         s256.result(&mut ret);
         // println!("{}: {}",v, hex::encode(ret.clone()));
         t.try_update(ret.clone(), Some(vs.to_vec())).unwrap();
@@ -74,7 +32,7 @@ fn main() {
         let vs = v.to_le_bytes();
         s256.input(&vs);
         
-        let mut ret = Vec::from_iter(std::iter::repeat(0_u8).take(ret_size));
+        let mut ret = vec![0_u8; ret_size];
         s256.result(&mut ret);
 
         if v & 1 == 0 {
@@ -99,7 +57,7 @@ fn main() {
     // println!("{}", d);
     println!("hash {} len {}", s256.result_str(), d.len());
     let st = now();
-    println!("root hash {}", t.hash());
+    println!("root hash {}", t.hash().unwrap());
     println!("{:?}", now()-st);
 
 }
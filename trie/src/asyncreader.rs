@@ -0,0 +1,227 @@
+use std::{cell::RefCell, future::Future, rc::Rc};
+
+use futures::future::join_all;
+
+use crate::{cache::{NodeCache, DEFAULT_CACHE_SIZE}, common::{keccak256, key_to_hex, Hash}, hasher::Hasher, node::{decode_node, HashNode, NilNode, Node, NodeType}, resolver::Resolver, Trie, TrieError, ID};
+
+// 异步遍历时对经过的每个节点的回调
+type NodeVisitor<'a> = dyn FnMut(&Rc<dyn Node>) -> Result<(), TrieError> + 'a;
+
+// 节点存储的异步读取接口, 用于远程或较慢的存储
+pub trait AsyncNodeReader {
+    // 读取节点数据, 不存在时返回None
+    fn get_node(&self, owner: Hash, path: &[u8], hash: Hash) -> impl Future<Output = Result<Option<Vec<u8>>, TrieError>>;
+}
+
+impl Trie {
+    // 异步打开trie, 只加载root, 其余节点由get_async/prove_async按需加载到节点缓存
+    pub async fn open_async<R: AsyncNodeReader>(id: ID, reader: &R) -> Result<Self, TrieError> {
+        Trie::open_async_with_cache(id, reader, Rc::new(RefCell::new(NodeCache::new(DEFAULT_CACHE_SIZE)))).await
+    }
+    // 使用指定的节点缓存异步打开trie, 已缓存的节点不再读取
+    pub async fn open_async_with_cache<R: AsyncNodeReader>(id: ID, reader: &R, cache: Rc<RefCell<NodeCache>>) -> Result<Self, TrieError> {
+        let mut t = Trie { root: Rc::new(NilNode), owner: id.owner, unhashed: 0, journal: Vec::new(), resolver: Resolver::with_cache(id.owner, None, cache) };
+        if !id.root.is_empty_root() {
+            t.root = t.resolve_async(reader, id.root, &[]).await?;
+        }
+        Ok(t)
+    }
+
+    // 异步查找key, 经过fullNode时把未加载的兄弟节点一起并发读取
+    pub async fn get_async<R: AsyncNodeReader>(&self, reader: &R, key: &[u8]) -> Result<Option<Vec<u8>>, TrieError> {
+        self.walk_async(reader, &key_to_hex(key), &mut |_| Ok(())).await
+    }
+
+    // 异步生成key的证明, 直接使用查找过程中解析到的节点, 不依赖节点缓存
+    pub async fn prove_async<R: AsyncNodeReader>(&mut self, reader: &R, key: &[u8]) -> Result<Vec<Vec<u8>>, TrieError> {
+        self.hash()?;
+        let mut h = Hasher::new(false);
+        let mut proof = Vec::new();
+        self.walk_async(reader, &key_to_hex(key), &mut |n| {
+            // 有hash的节点单独存储, 没有hash的节点内嵌在父节点中
+            if let (Some(_), _) = n.cache() {
+                proof.push(h.encode_node(Rc::clone(n))?);
+            }
+            Ok(())
+        }).await?;
+        Ok(proof)
+    }
+
+    // 沿hex路径异步向下走, 对经过的每个节点调用f, 返回key对应的值
+    async fn walk_async<R: AsyncNodeReader>(&self, reader: &R, key: &[u8], f: &mut NodeVisitor<'_>) -> Result<Option<Vec<u8>>, TrieError> {
+        let mut n = Rc::clone(&self.root);
+        let mut pos = 0;
+        loop {
+            // 已在内存中的存储节点也要记录到witness
+            self.resolver.touch(&n)?;
+            f(&n)?;
+            match n.kind() {
+                NodeType::NullNode => return Ok(None),
+                NodeType::ValueNode => return Ok(Some(n.into_value_node()?.0)),
+                NodeType::ShortNode => {
+                    let sn = n.into_short_node()?;
+                    if !key[pos..].starts_with(&sn.key) {
+                        return Ok(None);
+                    }
+                    pos += sn.key.len();
+                    n = sn.val;
+                },
+                NodeType::FullNode => {
+                    if pos >= key.len() {
+                        return Err(TrieError::invalid("full node at end of key"));
+                    }
+                    let f_n = n.into_full_node()?;
+                    let child = match &f_n.children[key[pos] as usize] {
+                        Some(child) => Rc::clone(child),
+                        None => return Ok(None),
+                    };
+                    if child.kind() == NodeType::HashNode {
+                        self.prefetch_children(reader, &f_n.children, &key[..pos]).await?;
+                    }
+                    n = child;
+                    pos += 1;
+                },
+                NodeType::HashNode => {
+                    let hn = n.into_hash_node()?;
+                    n = self.resolve_async(reader, Hash::from(hn.0), &key[..pos]).await?;
+                },
+            }
+        }
+    }
+
+    async fn resolve_async<R: AsyncNodeReader>(&self, reader: &R, hash: Hash, path: &[u8]) -> Result<Rc<dyn Node>, TrieError> {
+        // 与同步解析一样记录加载路径和witness
+        let cache = self.resolver.cache();
+        let cached = cache.borrow_mut().get(&hash);
+        if let Some(n) = cached {
+            self.resolver.record(hash, path, &n, None)?;
+            return Ok(n);
+        }
+        let reqs = [(path.to_vec(), hash)];
+        match fetch_nodes(reader, self.owner, &cache, &reqs).await.pop() {
+            Some(Ok(Some((n, blob)))) => {
+                self.resolver.record(hash, path, &n, Some(blob))?;
+                Ok(n)
+            },
+            Some(Err(e)) => Err(e),
+            _ => Err(TrieError::missing(path, hash)),
+        }
+    }
+
+    // 并发读取fullNode下所有未缓存的子节点, 读取失败的子节点留到真正访问时再报错
+    async fn prefetch_children<R: AsyncNodeReader>(&self, reader: &R, children: &[Option<Rc<dyn Node>>], path: &[u8]) -> Result<(), TrieError> {
+        let cache = self.resolver.cache();
+        let mut reqs = Vec::new();
+        for (i, child) in children.iter().enumerate().take(16) {
+            if let Some(child) = child {
+                if child.kind() != NodeType::HashNode {
+                    continue;
+                }
+                let hash = Hash::from(child.into_hash_node()?.0);
+                if cache.borrow().contains(&hash) {
+                    continue;
+                }
+                let mut child_path = path.to_vec();
+                child_path.push(i as u8);
+                reqs.push((child_path, hash));
+            }
+        }
+        fetch_nodes(reader, self.owner, &cache, &reqs).await;
+        Ok(())
+    }
+}
+
+// 并发读取reqs中的(hex路径, hash), 解码后放入缓存, 返回节点及其原始数据; 节点不存在或hash不符时对应结果为None
+pub(crate) async fn fetch_nodes<R: AsyncNodeReader>(reader: &R, owner: Hash, cache: &RefCell<NodeCache>, reqs: &[(Vec<u8>, Hash)]) -> Vec<Result<Option<(Rc<dyn Node>, Vec<u8>)>, TrieError>> {
+    let blobs = join_all(reqs.iter().map(|(path, hash)| reader.get_node(owner, path, *hash))).await;
+    let mut ret = Vec::with_capacity(reqs.len());
+    for ((_, hash), blob) in reqs.iter().zip(blobs) {
+        let blob = match blob {
+            Ok(Some(blob)) if keccak256(&blob) == *hash => blob,
+            Ok(_) => {
+                ret.push(Ok(None));
+                continue;
+            },
+            Err(e) => {
+                ret.push(Err(e));
+                continue;
+            },
+        };
+        ret.push(decode_node(Some(HashNode::from(**hash)), &blob).map(|n| {
+            cache.borrow_mut().insert(*hash, Rc::clone(&n), blob.len());
+            Some((n, blob))
+        }));
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::{MemoryDB, NodeReader}, proof::verify_proof};
+
+    struct Store(MemoryDB);
+
+    impl AsyncNodeReader for Store {
+        async fn get_node(&self, owner: Hash, path: &[u8], hash: Hash) -> Result<Option<Vec<u8>>, TrieError> {
+            tokio::task::yield_now().await;
+            self.0.node(owner, path, hash)
+        }
+    }
+
+    fn build() -> (Hash, Store) {
+        let mut t = Trie::new(ID::trie_id(Hash::default()));
+        for i in 0..500u32 {
+            t.try_update(keccak256(&i.to_be_bytes()).to_vec(), Some(vec![i as u8; 1 + (i % 40) as usize])).unwrap();
+        }
+        let (root, set) = t.commit().unwrap();
+        let db = MemoryDB::new();
+        db.update(&set);
+        (root, Store(db))
+    }
+
+    #[tokio::test]
+    async fn get_and_prove() {
+        let (root, store) = build();
+        let mut t = Trie::open_async(ID::trie_id(root), &store).await.unwrap();
+        for i in (0..500u32).step_by(7) {
+            let key = keccak256(&i.to_be_bytes()).to_vec();
+            let value = Some(vec![i as u8; 1 + (i % 40) as usize]);
+            assert_eq!(t.get_async(&store, &key).await.unwrap(), value);
+            let proof = t.prove_async(&store, &key).await.unwrap();
+            assert_eq!(verify_proof(root, &key, &proof).unwrap(), value);
+        }
+        assert_eq!(t.get_async(&store, b"missing").await.unwrap(), None);
+    }
+
+    // 缓存放不下路径上的节点时, 证明仍由查找过程中解析到的节点生成
+    #[tokio::test]
+    async fn prove_with_small_cache() {
+        let (root, store) = build();
+        let cache = Rc::new(RefCell::new(NodeCache::new(64)));
+        let mut t = Trie::open_async_with_cache(ID::trie_id(root), &store, cache).await.unwrap();
+        for i in (0..500u32).step_by(31) {
+            let key = keccak256(&i.to_be_bytes()).to_vec();
+            let proof = t.prove_async(&store, &key).await.unwrap();
+            assert_eq!(verify_proof(root, &key, &proof).unwrap(), Some(vec![i as u8; 1 + (i % 40) as usize]));
+        }
+    }
+
+    // 异步解析的节点同样记录到witness和加载路径中, 包括第一次读取和缓存命中
+    #[tokio::test]
+    async fn records_witness() {
+        let (root, store) = build();
+        let key = keccak256(&3u32.to_be_bytes()).to_vec();
+        let mut t = Trie::open_async(ID::trie_id(root), &store).await.unwrap();
+        for _ in 0..2 {
+            t.start_witness();
+            t.get_async(&store, &key).await.unwrap();
+            let w = t.stop_witness().unwrap();
+            let replay = Trie::from_witness(root, &w.nodes()).unwrap();
+            assert_eq!(replay.get(&key).unwrap(), Some(vec![3; 4]));
+        }
+        let loaded = t.resolver.loaded_paths();
+        assert!(loaded.len() > 1);
+        assert!(loaded.iter().all(|p| key_to_hex(&key).starts_with(p)));
+    }
+}
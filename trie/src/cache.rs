@@ -0,0 +1,175 @@
+use std::{rc::Rc, collections::{BTreeMap, HashMap}};
+
+use crate::{common::Hash, node::Node};
+
+// 默认缓存大小16MB
+pub const DEFAULT_CACHE_SIZE: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+// 已解码干净节点的LRU缓存, 以节点编码后的字节数计算容量
+pub struct NodeCache {
+    max_bytes: usize,
+    bytes: usize,
+    // hash -> (节点, 字节数, 最近访问序号)
+    entries: HashMap<Hash, (Rc<dyn Node>, usize, u64)>,
+    // 访问序号 -> hash, 最小的最先淘汰
+    order: BTreeMap<u64, Hash>,
+    tick: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl NodeCache {
+    pub fn new(max_bytes: usize) -> Self {
+        NodeCache { max_bytes, bytes: 0, entries: HashMap::new(), order: BTreeMap::new(), tick: 0, hits: 0, misses: 0 }
+    }
+
+    pub fn get(&mut self, hash: &Hash) -> Option<Rc<dyn Node>> {
+        let tick = self.next_tick();
+        match self.entries.get_mut(hash) {
+            Some((n, _, last)) => {
+                self.order.remove(last);
+                self.order.insert(tick, *hash);
+                *last = tick;
+                self.hits += 1;
+                Some(Rc::clone(n))
+            },
+            None => {
+                self.misses += 1;
+                None
+            },
+        }
+    }
+
+    // 放入节点, size为节点编码后的字节数, 超出容量时淘汰最久未访问的节点
+    pub fn insert(&mut self, hash: Hash, n: Rc<dyn Node>, size: usize) {
+        if size > self.max_bytes {
+            return;
+        }
+        self.remove(&hash);
+        let tick = self.next_tick();
+        self.entries.insert(hash, (n, size, tick));
+        self.order.insert(tick, hash);
+        self.bytes += size;
+        while self.bytes > self.max_bytes {
+            let oldest = match self.order.iter().next() {
+                Some((_, hash)) => *hash,
+                None => break,
+            };
+            self.remove(&oldest);
+        }
+    }
+
+    pub fn remove(&mut self, hash: &Hash) -> bool {
+        match self.entries.remove(hash) {
+            Some((_, size, last)) => {
+                self.order.remove(&last);
+                self.bytes -= size;
+                true
+            },
+            None => false,
+        }
+    }
+
+    // 读取节点但不计入命中统计, 也不更新访问顺序, 用于预取等探测
+    pub fn peek(&self, hash: &Hash) -> Option<Rc<dyn Node>> {
+        self.entries.get(hash).map(|(n, _, _)| Rc::clone(n))
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.entries.contains_key(hash)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats { hits: self.hits, misses: self.misses, entries: self.entries.len(), bytes: self.bytes }
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.hits = 0;
+        self.misses = 0;
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::{common::keccak256, database::MemoryDB, node::ValueNode, Trie, ID};
+
+    fn node(i: u8) -> (Hash, Rc<dyn Node>) {
+        (keccak256(&[i]), Rc::new(ValueNode::new(vec![i])))
+    }
+
+    // 超出容量时淘汰最久未访问的节点, get会更新访问顺序
+    #[test]
+    fn lru_eviction() {
+        let mut c = NodeCache::new(30);
+        let (h1, n1) = node(1);
+        let (h2, n2) = node(2);
+        let (h3, n3) = node(3);
+        c.insert(h1, n1, 10);
+        c.insert(h2, n2, 10);
+        c.insert(h3, Rc::clone(&n3), 10);
+        assert!(c.get(&h1).is_some());
+        let (h4, n4) = node(4);
+        c.insert(h4, n4, 10);
+        assert!(c.contains(&h1) && !c.contains(&h2) && c.contains(&h3) && c.contains(&h4));
+        assert_eq!(c.stats(), CacheStats { hits: 1, misses: 0, entries: 3, bytes: 30 });
+
+        // 重复插入不重复计算大小, 超过容量的节点不缓存
+        c.insert(h3, n3, 10);
+        assert_eq!(c.stats().bytes, 30);
+        let (h5, n5) = node(5);
+        c.insert(h5, n5, 31);
+        assert!(!c.contains(&h5));
+        assert!(c.get(&h5).is_none());
+        assert_eq!(c.stats().misses, 1);
+
+        assert!(c.remove(&h1));
+        assert!(!c.remove(&h1));
+        assert_eq!(c.stats().bytes, 20);
+        c.reset_stats();
+        assert_eq!((c.stats().hits, c.stats().misses), (0, 0));
+    }
+
+    // 共享缓存的第二个trie不再读取存储, 全部命中
+    #[test]
+    fn shared_cache_hits() {
+        let mut t = Trie::new(ID::trie_id(Hash::default()));
+        for i in 0..200u32 {
+            t.try_update(keccak256(&i.to_be_bytes()).to_vec(), Some(vec![i as u8; 40])).unwrap();
+        }
+        let (root, set) = t.commit().unwrap();
+        let db = Rc::new(MemoryDB::new());
+        db.update(&set);
+        let cache = Rc::new(RefCell::new(NodeCache::new(DEFAULT_CACHE_SIZE)));
+        let a = Trie::open_with_cache(ID::trie_id(root), db.clone(), Rc::clone(&cache)).unwrap();
+        for i in 0..200u32 {
+            a.get(keccak256(&i.to_be_bytes()).as_slice()).unwrap();
+        }
+        let stats = a.cache_stats();
+        assert_eq!(stats.entries, set.len());
+        assert_eq!(stats.misses as usize, set.len());
+
+        cache.borrow_mut().reset_stats();
+        let b = Trie::open_with_cache(ID::trie_id(root), Rc::new(MemoryDB::new()), cache).unwrap();
+        for i in 0..200u32 {
+            assert_eq!(b.get(keccak256(&i.to_be_bytes()).as_slice()).unwrap(), Some(vec![i as u8; 40]));
+        }
+        assert_eq!(b.cache_stats().misses, 0);
+        assert!(b.cache_stats().hits >= 200);
+    }
+}
@@ -0,0 +1,57 @@
+use std::rc::Rc;
+
+use crate::{common::Hash, hasher::Hasher, node::{HashNode, Node, NodeType}, nodeset::{NodeSet, TrieNode}, TrieError};
+
+// 收集已计算hash的脏节点, 并把它们标记为干净节点
+pub(crate) struct Committer<'a> {
+    set: &'a mut NodeSet,
+    hasher: Hasher,
+}
+
+impl<'a> Committer<'a> {
+    pub(crate) fn new(set: &'a mut NodeSet) -> Self {
+        Committer { set, hasher: Hasher::new(false) }
+    }
+
+    // 提交节点n及其下的脏节点, 返回干净的节点, 调用前需先计算hash
+    pub(crate) fn commit(&mut self, n: Rc<dyn Node>, path: Vec<u8>) -> Result<Rc<dyn Node>, TrieError> {
+        let (hash, dirty) = n.cache();
+        if !dirty {
+            return Ok(n);
+        }
+        match n.kind() {
+            NodeType::ShortNode => {
+                let mut sn = n.into_short_node()?;
+                if sn.val.kind() == NodeType::ShortNode || sn.val.kind() == NodeType::FullNode {
+                    let mut child_path = path.clone();
+                    child_path.extend(&sn.key);
+                    sn.val = self.commit(sn.val, child_path)?;
+                }
+                sn.flags.dirty = false;
+                self.store(path, hash, Rc::new(sn))
+            },
+            NodeType::FullNode => {
+                let mut f_n = n.into_full_node()?;
+                for i in 0..16 {
+                    if let Some(child) = &f_n.children[i] {
+                        let mut child_path = path.clone();
+                        child_path.push(i as u8);
+                        f_n.children[i] = Some(self.commit(Rc::clone(child), child_path)?);
+                    }
+                }
+                f_n.flags.dirty = false;
+                self.store(path, hash, Rc::new(f_n))
+            },
+            _ => Ok(n),
+        }
+    }
+
+    fn store(&mut self, path: Vec<u8>, hash: Option<HashNode>, n: Rc<dyn Node>) -> Result<Rc<dyn Node>, TrieError> {
+        // 没有hash的是内嵌节点, 随父节点一起存储
+        if let Some(hn) = hash {
+            let blob = self.hasher.encode_node(Rc::clone(&n))?;
+            self.set.add_node(path, TrieNode { hash: Hash::from(hn.0), blob });
+        }
+        Ok(n)
+    }
+}
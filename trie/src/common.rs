@@ -1,26 +1,31 @@
 use std::ops::Deref;
 
-#[derive(Clone,Copy)]
+use crypto::{digest::Digest, sha3::Sha3};
+
+use crate::TrieError;
+
+#[derive(Clone,Copy,Debug,Default,PartialEq,Eq,Hash,PartialOrd,Ord)]
 pub struct Hash([u8;32]);
 
 impl Hash {
-    pub fn default() -> Self {
-        Hash([0_u8;32])
-    }
     pub fn from(v: [u8;32]) -> Self {
         Hash(v)
     }
+    // 空trie的root, 即keccak256(rlp(""))
     pub fn empty_root_hash() -> Self {
-        let mas_ch = "I love rose elder sister";
-        // let mut arr: [u8; 32] = (0..32).collect::<Vec<u8>>().try_into().unwrap();
-        let mut arr = [0_u8; 32];
-        for (i, v) in mas_ch.as_bytes().iter().enumerate() {
-            arr[i] = *v;
-        }
-        Hash(arr)
+        Hash(EMPTY_ROOT)
+    }
+    // 是否为空trie的root, 全0的Hash::default()不是空root
+    pub fn is_empty_root(&self) -> bool {
+        self.0 == EMPTY_ROOT
     }
 }
 
+const EMPTY_ROOT: [u8; 32] = [
+    0x56, 0xe8, 0x1f, 0x17, 0x1b, 0xcc, 0x55, 0xa6, 0xff, 0x83, 0x45, 0xe6, 0x92, 0xc0, 0xf8, 0x6e,
+    0x5b, 0x48, 0xe0, 0x1b, 0x99, 0x6c, 0xad, 0xc0, 0x01, 0x62, 0x2f, 0xb5, 0xe3, 0x63, 0xb4, 0x21,
+];
+
 impl Deref for Hash {
     type Target = [u8;32];
 
@@ -30,9 +35,25 @@ impl Deref for Hash {
 }
 impl std::fmt::Display for Hash {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        println!("0x{}", hex::encode(self.0));
-        Ok(())
+        write!(f, "0x{}", hex::encode(self.0))
+    }
+}
+
+pub fn keccak256(data: &[u8]) -> Hash {
+    let mut hasher = Sha3::keccak256();
+    hasher.input(data);
+    let mut out = [0_u8; 32];
+    hasher.result(&mut out);
+    Hash(out)
+}
+
+pub(crate) fn to_hash(buf: &[u8]) -> Result<Hash, TrieError> {
+    if buf.len() != 32 {
+        return Err(TrieError::DecodeError(format!("invalid hash length {}", buf.len())));
     }
+    let mut hs = [0_u8; 32];
+    hs.copy_from_slice(buf);
+    Ok(Hash::from(hs))
 }
 
 // key扩展
@@ -46,13 +67,24 @@ pub(crate) fn key_to_hex(key: &[u8]) -> Vec<u8> {
     bt
 }
 
+// hex路径还原成key, 忽略末尾的终止符
+pub(crate) fn hex_to_key(hex: &[u8]) -> Vec<u8> {
+    let mut hex = hex;
+    if has_term(hex) {
+        hex = &hex[..hex.len()-1];
+    }
+    let mut key = vec![0_u8; hex.len()/2];
+    decode_nibbles(hex, &mut key);
+    key
+}
+
 pub(crate) fn hex_to_compact(mut data: &[u8]) -> Vec<u8> {
     let mut terminator = 0_u8;
     if has_term(data) { // key的末尾是否有终止符, 没有表示扩展节点，有表示叶子节点
         terminator = 1;
         data = &data[..data.len()-1];
     }
-    let mut buf = Vec::from_iter(std::iter::repeat(0_u8).take(data.len()/2+1));
+    let mut buf = vec![0_u8; data.len()/2+1];
     // let mut buf = Vec::<u8>::with_capacity(data.len()/2+1);
     // buf.push(terminator << 5);
     buf[0] = terminator << 5;
@@ -67,8 +99,22 @@ pub(crate) fn hex_to_compact(mut data: &[u8]) -> Vec<u8> {
     buf
 }
 
-fn has_term(data: &[u8]) -> bool {
-    data.len() > 0 && data[data.len()-1] == 16
+pub(crate) fn compact_to_hex(compact: &[u8]) -> Vec<u8> {
+    if compact.is_empty() {
+        return Vec::new();
+    }
+    let mut base = key_to_hex(compact);
+    // 叶子节点保留终止符
+    if base[0] < 2 {
+        base.truncate(base.len()-1);
+    }
+    // 奇数长度去掉1个标志位, 偶数长度去掉2个
+    let chop = 2 - (base[0] & 1) as usize;
+    base.split_off(chop)
+}
+
+pub(crate) fn has_term(data: &[u8]) -> bool {
+    data.last() == Some(&16)
 }
 
 fn decode_nibbles(nibbles: &[u8], bytes: &mut [u8]) {
@@ -0,0 +1,75 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use crate::{common::Hash, nodeset::NodeSet, TrieError};
+
+// 节点存储的读取接口, owner区分存储trie, path为节点的hex路径
+pub trait NodeReader {
+    // 读取节点数据, 不存在时返回None
+    fn node(&self, owner: Hash, path: &[u8], hash: Hash) -> Result<Option<Vec<u8>>, TrieError>;
+    // 批量读取节点, 结果与reqs一一对应, 远程存储可覆盖实现合并请求
+    fn nodes(&self, owner: Hash, reqs: &[(Vec<u8>, Hash)]) -> Result<Vec<Option<Vec<u8>>>, TrieError> {
+        reqs.iter().map(|(path, hash)| self.node(owner, path, *hash)).collect()
+    }
+    // 读取存储的原始数据, 不校验hash; 读取时会校验hash的实现需覆盖, 用于检查数据损坏
+    fn unchecked_node(&self, owner: Hash, path: &[u8], hash: Hash) -> Result<Option<Vec<u8>>, TrieError> {
+        self.node(owner, path, hash)
+    }
+}
+
+// 节点存储的写入接口, 写入一次提交或同步得到的节点集合
+pub trait NodeWriter {
+    fn write_nodes(&self, set: &NodeSet) -> Result<(), TrieError>;
+}
+
+// 以hash为key的内存节点库
+pub struct MemoryDB {
+    nodes: RefCell<HashMap<Hash, Vec<u8>>>,
+}
+
+impl MemoryDB {
+    pub fn new() -> Self {
+        MemoryDB { nodes: RefCell::new(HashMap::new()) }
+    }
+    pub fn insert(&self, hash: Hash, blob: Vec<u8>) {
+        self.nodes.borrow_mut().insert(hash, blob);
+    }
+    // 写入一次提交的所有节点
+    pub fn update(&self, set: &NodeSet) {
+        let mut nodes = self.nodes.borrow_mut();
+        for (_, n) in set.iter() {
+            // 按hash存储时不需要处理删除
+            if n.is_deleted() {
+                continue;
+            }
+            nodes.insert(n.hash, n.blob.clone());
+        }
+    }
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.nodes.borrow().contains_key(hash)
+    }
+    pub fn len(&self) -> usize {
+        self.nodes.borrow().len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.nodes.borrow().is_empty()
+    }
+}
+
+impl Default for MemoryDB {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NodeReader for MemoryDB {
+    fn node(&self, _: Hash, _: &[u8], hash: Hash) -> Result<Option<Vec<u8>>, TrieError> {
+        Ok(self.nodes.borrow().get(&hash).cloned())
+    }
+}
+
+impl NodeWriter for MemoryDB {
+    fn write_nodes(&self, set: &NodeSet) -> Result<(), TrieError> {
+        self.update(set);
+        Ok(())
+    }
+}
@@ -0,0 +1,96 @@
+use std::{error::Error, fmt};
+
+use crate::common::Hash;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrieError {
+    // 节点不在内存中, path为节点所在的hex路径, 调用方可加载后重试
+    MissingNode { path: Vec<u8>, hash: Hash },
+    // 节点类型或结构不符合预期
+    InvalidNode(String),
+    // 节点数据解码失败
+    DecodeError(String),
+    // 底层存储读写失败
+    BackendError(String),
+    // 证明校验失败
+    InvalidProof(String),
+    // 检查点不存在或已失效
+    UnknownCheckpoint(usize),
+}
+
+impl TrieError {
+    pub(crate) fn missing(path: &[u8], hash: Hash) -> Self {
+        TrieError::MissingNode { path: path.to_vec(), hash }
+    }
+    pub(crate) fn invalid(v: &str) -> Self {
+        TrieError::InvalidNode(v.to_string())
+    }
+}
+
+impl fmt::Display for TrieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TrieError::MissingNode { path, hash } => write!(f, "missing trie node {} (path {})", hash, path.iter().map(|v| format!("{:x}", v)).collect::<String>()),
+            TrieError::InvalidNode(v) => write!(f, "invalid node: {}", v),
+            TrieError::DecodeError(v) => write!(f, "decode error: {}", v),
+            TrieError::BackendError(v) => write!(f, "backend error: {}", v),
+            TrieError::InvalidProof(v) => write!(f, "invalid proof: {}", v),
+            TrieError::UnknownCheckpoint(id) => write!(f, "unknown checkpoint {}", id),
+        }
+    }
+}
+
+impl Error for TrieError {}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::{common::keccak256, database::MemoryDB, node::decode_node, Trie, ID};
+
+    // 存储中只有root时, 查找、更新和删除都返回缺失的节点而不是panic
+    #[test]
+    fn missing_node_is_typed() {
+        let mut t = Trie::new(ID::trie_id(Hash::default()));
+        for i in 0..100u32 {
+            t.try_update(keccak256(&i.to_be_bytes()).to_vec(), Some(vec![i as u8; 40])).unwrap();
+        }
+        let (root, set) = t.commit().unwrap();
+        let db = MemoryDB::new();
+        let root_blob = set.iter().find(|(path, _)| path.is_empty()).unwrap().1.blob.clone();
+        db.insert(root, root_blob);
+
+        let key = keccak256(&7u32.to_be_bytes()).to_vec();
+        let mut t = Trie::open(ID::trie_id(root), Rc::new(db)).unwrap();
+        let err = t.get(&key).unwrap_err();
+        match &err {
+            TrieError::MissingNode { path, hash } => {
+                assert_eq!(path.len(), 1);
+                assert_eq!(path[0], key[0] >> 4);
+                assert!(set.iter().any(|(_, n)| n.hash == *hash));
+            },
+            e => panic!("unexpected error {:?}", e),
+        }
+        assert!(err.to_string().starts_with("missing trie node"));
+        assert_eq!(t.try_update(key.clone(), Some(vec![1])), Err(err.clone()));
+        assert_eq!(t.try_update(key, None), Err(err));
+        // 失败的操作不改变trie
+        assert_eq!(t.hash().unwrap(), root);
+    }
+
+    #[test]
+    fn decode_errors() {
+        assert!(matches!(decode_node(None, &[]), Err(TrieError::DecodeError(_))));
+        assert!(matches!(decode_node(None, &[0xc3, 0x80]), Err(TrieError::DecodeError(_))));
+        assert!(Trie::from_witness(keccak256(b"x"), &[vec![0xc0]]).is_err());
+    }
+
+    #[test]
+    fn display() {
+        let e = TrieError::missing(&[1, 0xa], Hash::default());
+        assert_eq!(e.to_string(), format!("missing trie node {} (path 1a)", Hash::default()));
+        assert_eq!(TrieError::UnknownCheckpoint(3).to_string(), "unknown checkpoint 3");
+        assert_eq!(TrieError::invalid("x").to_string(), "invalid node: x");
+    }
+}
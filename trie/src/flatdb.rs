@@ -0,0 +1,355 @@
+use std::{collections::{BTreeMap, VecDeque}, rc::Rc};
+
+use crate::{common::{hex_to_key, to_hash, Hash}, kvstore::KeyValueStore, node::{decode_node, Node, NodeType}, nodeset::NodeSet, Trie, TrieError, ID};
+
+// 扁平键值的key前缀, 后接trie中的key(通常已是hash)
+// 与HashDB(n/r前缀)、PathDB(A/O/H前缀)的key互不重叠, 可以共用同一个KeyValueStore
+pub const FLAT_PREFIX: &[u8] = b"S";
+// 扁平存储当前对应的trie root
+pub const FLAT_ROOT_KEY: &[u8] = b"flat-root";
+
+const SCAN_BATCH: usize = 256;
+
+// trie的扁平键值快照, O(1)读取, 与trie提交同步更新并记录对应的root
+pub struct FlatStore {
+    disk: Rc<dyn KeyValueStore>,
+}
+
+impl FlatStore {
+    pub fn new(disk: Rc<dyn KeyValueStore>) -> Self {
+        FlatStore { disk }
+    }
+
+    // 当前对应的root, 尚未生成时返回None
+    pub fn root(&self) -> Result<Option<Hash>, TrieError> {
+        match self.disk.get(FLAT_ROOT_KEY)? {
+            Some(buf) => Ok(Some(to_hash(&buf)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, TrieError> {
+        self.disk.get(&flat_key(key))
+    }
+
+    // 从start(含)开始按key顺序遍历
+    pub fn iter(&self, start: &[u8]) -> FlatIterator {
+        FlatIterator { disk: Rc::clone(&self.disk), cursor: flat_key(start), buf: VecDeque::new(), done: false }
+    }
+
+    // 应用一次提交的键值变更, value为None表示删除, parent须与当前root一致
+    pub fn update(&self, parent: Hash, root: Hash, changes: &[(Vec<u8>, Option<Vec<u8>>)]) -> Result<(), TrieError> {
+        self.check_parent(parent)?;
+        for (key, value) in changes.iter() {
+            match value {
+                Some(value) => self.disk.put(&flat_key(key), value)?,
+                None => self.disk.delete(&flat_key(key))?,
+            }
+        }
+        self.disk.put(FLAT_ROOT_KEY, root.as_slice())
+    }
+
+    // 提交trie并同步更新扁平存储, 扁平存储须处于trie打开时的root
+    pub fn commit(&self, trie: &mut Trie) -> Result<(Hash, NodeSet), TrieError> {
+        let parent = self.root()?.unwrap_or_else(Hash::empty_root_hash);
+        let (root, set) = trie.commit()?;
+        self.apply(parent, root, &set)?;
+        Ok((root, set))
+    }
+
+    // 从一次提交的NodeSet推导键值变更并应用, parent须与当前root一致
+    // 新节点引用的未修改节点下的键值不变, 其余范围内的旧键值按新节点中的叶子重写或删除
+    pub fn apply(&self, parent: Hash, root: Hash, set: &NodeSet) -> Result<(), TrieError> {
+        self.check_parent(parent)?;
+        let mut leaves = BTreeMap::new();
+        let mut clean = Vec::new();
+        for (path, n) in set.iter() {
+            if !n.is_deleted() {
+                collect_leaves(&decode_node(None, &n.blob)?, path.clone(), set, &mut leaves, &mut clean)?;
+            }
+        }
+        clean.sort();
+        // 变更范围的起点是NodeSet中最上层的路径, trie被清空时删除的节点可能没有记录, 直接从根开始
+        let mut tops: Vec<&[u8]> = Vec::new();
+        if root.is_empty_root() {
+            tops.push(&[]);
+        } else {
+            for path in set.iter().map(|(path, _)| path) {
+                if !tops.last().is_some_and(|top| path.starts_with(top)) {
+                    tops.push(path);
+                }
+            }
+        }
+        for top in tops {
+            let mut lo = Some(top.to_vec());
+            for x in clean.iter().filter(|x| x.starts_with(top)) {
+                if let Some(from) = lo {
+                    self.delete_stale(&from, Some(x), &leaves)?;
+                }
+                lo = next_path(x);
+            }
+            if let Some(from) = lo {
+                self.delete_stale(&from, next_path(top).as_deref(), &leaves)?;
+            }
+        }
+        for (key, value) in leaves.iter() {
+            self.disk.put(&flat_key(key), value)?;
+        }
+        self.disk.put(FLAT_ROOT_KEY, root.as_slice())
+    }
+
+    // 清空后从trie重新生成, 返回写入的键值数量
+    pub fn generate(&self, trie: &mut Trie) -> Result<usize, TrieError> {
+        self.wipe()?;
+        let root = trie.hash()?;
+        let mut count = 0;
+        for item in trie.iter() {
+            let (key, value) = item?;
+            self.disk.put(&flat_key(&key), &value)?;
+            count += 1;
+        }
+        self.disk.put(FLAT_ROOT_KEY, root.as_slice())?;
+        Ok(count)
+    }
+
+    // 用扁平存储中的所有键值重建trie并计算root
+    pub fn compute_root(&self) -> Result<Hash, TrieError> {
+        let mut t = Trie::new(ID::trie_id(Hash::empty_root_hash()));
+        for item in self.iter(&[]) {
+            let (key, value) = item?;
+            t.try_update(key, Some(value))?;
+        }
+        t.hash()
+    }
+
+    // 校验键值与记录的root是否一致
+    pub fn verify(&self) -> Result<bool, TrieError> {
+        match self.root()? {
+            Some(root) => Ok(self.compute_root()? == root),
+            None => Ok(false),
+        }
+    }
+
+    fn check_parent(&self, parent: Hash) -> Result<(), TrieError> {
+        let current = self.root()?.unwrap_or_else(Hash::empty_root_hash);
+        if current != parent && !(current.is_empty_root() && parent.is_empty_root()) {
+            return Err(TrieError::BackendError(format!("flat store is at {}, not at parent {}", current, parent)));
+        }
+        Ok(())
+    }
+
+    // 删除hex路径范围[from, to)内不在leaves中的键值, to为None表示到末尾
+    fn delete_stale(&self, from: &[u8], to: Option<&[u8]>, leaves: &BTreeMap<Vec<u8>, Vec<u8>>) -> Result<(), TrieError> {
+        let end = to.map(path_bound);
+        let mut stale = Vec::new();
+        for item in self.iter(&path_bound(from)) {
+            let (key, _) = item?;
+            if end.as_ref().is_some_and(|end| key >= *end) {
+                break;
+            }
+            if !leaves.contains_key(&key) {
+                stale.push(key);
+            }
+        }
+        for key in stale {
+            self.disk.delete(&flat_key(&key))?;
+        }
+        Ok(())
+    }
+
+    fn wipe(&self) -> Result<(), TrieError> {
+        let keys: Vec<Vec<u8>> = self.iter(&[]).map(|item| item.map(|(key, _)| flat_key(&key))).collect::<Result<_, _>>()?;
+        for key in keys {
+            self.disk.delete(&key)?;
+        }
+        self.disk.delete(FLAT_ROOT_KEY)
+    }
+}
+
+pub struct FlatIterator {
+    disk: Rc<dyn KeyValueStore>,
+    cursor: Vec<u8>,
+    buf: VecDeque<(Vec<u8>, Vec<u8>)>,
+    done: bool,
+}
+
+impl Iterator for FlatIterator {
+    type Item = Result<(Vec<u8>, Vec<u8>), TrieError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buf.is_empty() && !self.done {
+            let batch = match self.disk.scan(&self.cursor, SCAN_BATCH) {
+                Ok(batch) => batch,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                },
+            };
+            if batch.len() < SCAN_BATCH {
+                self.done = true;
+            }
+            for (key, value) in batch {
+                // 超出前缀范围后结束
+                if !key.starts_with(FLAT_PREFIX) {
+                    self.done = true;
+                    break;
+                }
+                self.cursor = key.clone();
+                self.cursor.push(0);
+                self.buf.push_back((key[FLAT_PREFIX.len()..].to_vec(), value));
+            }
+        }
+        self.buf.pop_front().map(Ok)
+    }
+}
+
+// 收集节点中的叶子, 以及引用的未修改存储节点的路径
+fn collect_leaves(n: &Rc<dyn Node>, path: Vec<u8>, set: &NodeSet, leaves: &mut BTreeMap<Vec<u8>, Vec<u8>>, clean: &mut Vec<Vec<u8>>) -> Result<(), TrieError> {
+    match n.kind() {
+        NodeType::ShortNode => {
+            let sn = n.into_short_node()?;
+            let mut child = path;
+            child.extend(&sn.key);
+            collect_leaves(&sn.val, child, set, leaves, clean)
+        },
+        NodeType::FullNode => {
+            let f_n = n.into_full_node()?;
+            for (i, c) in f_n.children.iter().enumerate() {
+                if let Some(c) = c {
+                    let mut child = path.clone();
+                    if i < 16 {
+                        child.push(i as u8);
+                    }
+                    collect_leaves(c, child, set, leaves, clean)?;
+                }
+            }
+            Ok(())
+        },
+        NodeType::ValueNode => {
+            leaves.insert(hex_to_key(&path), n.into_value_node()?.0);
+            Ok(())
+        },
+        NodeType::HashNode => {
+            if !set.contains(&path) {
+                clean.push(path);
+            }
+            Ok(())
+        },
+        NodeType::NullNode => Ok(()),
+    }
+}
+
+// hex路径对应的最小key, 奇数长度时最后一个半字节作为高4位
+fn path_bound(path: &[u8]) -> Vec<u8> {
+    path.chunks(2).map(|c| (c[0] << 4) | c.get(1).copied().unwrap_or(0)).collect()
+}
+
+// 不以path为前缀且大于path的最小路径, path下所有key都小于它, path全为f时返回None
+fn next_path(path: &[u8]) -> Option<Vec<u8>> {
+    let mut next = path.to_vec();
+    while let Some(last) = next.pop() {
+        if last < 15 {
+            next.push(last + 1);
+            return Some(next);
+        }
+    }
+    None
+}
+
+fn flat_key(key: &[u8]) -> Vec<u8> {
+    let mut k = FLAT_PREFIX.to_vec();
+    k.extend_from_slice(key);
+    k
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{common::keccak256, hashdb::HashDB, kvstore::MemoryStore};
+
+    fn flat_items(flat: &FlatStore) -> Vec<(Vec<u8>, Vec<u8>)> {
+        flat.iter(&[]).collect::<Result<_, _>>().unwrap()
+    }
+
+    // 每次提交后扁平存储与trie的内容一致, 包括长度不同、互为前缀的key和清空整个trie
+    #[test]
+    fn commit_keeps_lockstep() {
+        let flat = FlatStore::new(Rc::new(MemoryStore::new()));
+        let mut t = Trie::new(ID::trie_id(Hash::default()));
+        let mut seed = 7_u64;
+        let mut rand = move |n: u64| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) % n
+        };
+        for round in 0..30u64 {
+            for _ in 0..1 + rand(200) {
+                let i = rand(2000) as u32;
+                let mut key = i.to_be_bytes().to_vec();
+                key.truncate(1 + (i % 4) as usize);
+                if rand(3) == 0 {
+                    t.try_update(key, None).unwrap();
+                } else {
+                    t.try_update(key, Some(vec![round as u8; 1 + rand(40) as usize])).unwrap();
+                }
+            }
+            let (root, _) = flat.commit(&mut t).unwrap();
+            let items: Vec<(Vec<u8>, Vec<u8>)> = t.iter().collect::<Result<_, _>>().unwrap();
+            assert_eq!(flat_items(&flat), items);
+            assert_eq!(flat.root().unwrap(), Some(root));
+        }
+        let keys: Vec<Vec<u8>> = t.iter().map(|item| item.unwrap().0).collect();
+        for key in keys {
+            t.try_update(key, None).unwrap();
+        }
+        let (root, _) = flat.commit(&mut t).unwrap();
+        assert!(root.is_empty_root());
+        assert!(flat_items(&flat).is_empty());
+        assert!(flat.verify().unwrap());
+    }
+
+    #[test]
+    fn rejects_wrong_parent() {
+        let flat = FlatStore::new(Rc::new(MemoryStore::new()));
+        let mut t = Trie::new(ID::trie_id(Hash::default()));
+        t.try_update(b"key".to_vec(), Some(b"value".to_vec())).unwrap();
+        let (root, set) = t.commit().unwrap();
+        assert!(flat.apply(keccak256(b"other"), root, &set).is_err());
+        flat.apply(Hash::empty_root_hash(), root, &set).unwrap();
+        assert_eq!(flat.get(b"key").unwrap(), Some(b"value".to_vec()));
+        assert!(flat.update(Hash::empty_root_hash(), root, &[]).is_err());
+    }
+
+    #[test]
+    fn path_bounds() {
+        assert_eq!(path_bound(&[1, 2, 3]), vec![0x12, 0x30]);
+        assert_eq!(next_path(&[1, 15]), Some(vec![2]));
+        assert_eq!(next_path(&[15, 15]), None);
+    }
+
+    // 与HashDB共用存储时, 扁平存储的遍历和重建不会碰到trie节点
+    #[test]
+    fn shares_store_with_hashdb() {
+        let store = Rc::new(MemoryStore::new());
+        let db = Rc::new(HashDB::new(store.clone()));
+        let mut t = Trie::new(ID::trie_id(Hash::default()));
+        for i in 0..3000u32 {
+            t.try_update(keccak256(&i.to_be_bytes()).to_vec(), Some(vec![i as u8; 1 + (i % 40) as usize])).unwrap();
+        }
+        let (root, set) = t.commit().unwrap();
+        db.update(&set).unwrap();
+        db.reference(root).unwrap();
+        db.flush().unwrap();
+        let nodes = store.len();
+
+        let flat = FlatStore::new(store.clone());
+        let mut t = Trie::open(ID::trie_id(root), db.clone()).unwrap();
+        assert_eq!(flat.generate(&mut t).unwrap(), 3000);
+        assert_eq!(flat.iter(&[]).count(), 3000);
+        assert!(flat.verify().unwrap());
+        // 再次生成会先清空扁平键值
+        assert_eq!(flat.generate(&mut t).unwrap(), 3000);
+        assert_eq!(store.len(), nodes + 3000 + 1);
+        let t = Trie::open(ID::trie_id(root), Rc::new(HashDB::new(store.clone()))).unwrap();
+        assert_eq!(t.iter().count(), 3000);
+    }
+}
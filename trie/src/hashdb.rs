@@ -0,0 +1,340 @@
+use std::{cell::{Cell, RefCell}, collections::{HashMap, VecDeque}, rc::Rc};
+
+use crate::{common::Hash, database::{NodeReader, NodeWriter}, kvstore::KeyValueStore, node::child_hashes, nodeset::NodeSet, TrieError};
+
+// 磁盘上节点数据和引用计数的key前缀, 后接节点hash
+// 加前缀后可以与FlatStore、PathDB等共用同一个KeyValueStore
+pub const NODE_PREFIX: &[u8] = b"n";
+pub const REFCOUNT_PREFIX: &[u8] = b"r";
+
+struct CachedNode {
+    blob: Vec<u8>,
+    // 被父节点或外部root引用的次数
+    parents: u32,
+    // 写入序号, 与order中的序号不同说明那一项是节点被删除前留下的
+    seq: u64,
+}
+
+// 带引用计数的节点库, 多个root共享节点, 引用数归零的节点连同其独占的子节点一起删除
+// 新节点先保存在内存中, 通过cap写入磁盘, 磁盘上的节点同样保存引用计数
+pub struct HashDB {
+    disk: Rc<dyn KeyValueStore>,
+    dirties: RefCell<HashMap<Hash, CachedNode>>,
+    // 节点写入内存的顺序, 子节点在父节点之前, cap时按此顺序写盘
+    order: RefCell<VecDeque<(Hash, u64)>>,
+    seq: Cell<u64>,
+    dirties_size: Cell<usize>,
+}
+
+impl HashDB {
+    pub fn new(disk: Rc<dyn KeyValueStore>) -> Self {
+        HashDB { disk, dirties: RefCell::new(HashMap::new()), order: RefCell::new(VecDeque::new()), seq: Cell::new(0), dirties_size: Cell::new(0) }
+    }
+
+    // 写入一次提交的节点, 并给新节点引用的子节点增加引用计数
+    pub fn update(&self, set: &NodeSet) -> Result<(), TrieError> {
+        // 路径倒序, 子节点先于父节点写入
+        for (_, n) in set.nodes.iter().rev() {
+            // 不再被引用的节点由dereference回收
+            if n.is_deleted() {
+                continue;
+            }
+            if self.dirties.borrow().contains_key(&n.hash) || self.disk.get(&node_key(&n.hash))?.is_some() {
+                continue;
+            }
+            for (_, child) in child_hashes(&n.blob)? {
+                self.incref(child)?;
+            }
+            self.dirties_size.set(self.dirties_size.get() + n.blob.len() + 32);
+            let seq = self.seq.get() + 1;
+            self.seq.set(seq);
+            self.dirties.borrow_mut().insert(n.hash, CachedNode { blob: n.blob.clone(), parents: 0, seq });
+            self.order.borrow_mut().push_back((n.hash, seq));
+        }
+        Ok(())
+    }
+
+    // 给root增加一个外部引用
+    pub fn reference(&self, root: Hash) -> Result<(), TrieError> {
+        if root.is_empty_root() {
+            return Ok(());
+        }
+        self.incref(root)
+    }
+
+    // 去掉root的一个外部引用, 引用数归零的节点被删除
+    pub fn dereference(&self, root: Hash) -> Result<(), TrieError> {
+        if root.is_empty_root() {
+            return Ok(());
+        }
+        self.decref(root)
+    }
+
+    // 把内存中的节点按写入顺序刷到磁盘, 直到内存占用不超过limit字节
+    pub fn cap(&self, limit: usize) -> Result<(), TrieError> {
+        while self.dirties_size.get() > limit {
+            let (hash, seq) = match self.order.borrow_mut().pop_front() {
+                Some(v) => v,
+                None => break,
+            };
+            // 已被删除的节点跳过, 删除后重新写入的节点以新的位置为准
+            if self.dirties.borrow().get(&hash).map(|n| n.seq) != Some(seq) {
+                continue;
+            }
+            let n = self.dirties.borrow_mut().remove(&hash).unwrap();
+            self.disk.put(&node_key(&hash), &n.blob)?;
+            self.disk.put(&refcount_key(&hash), &n.parents.to_be_bytes())?;
+            self.dirties_size.set(self.dirties_size.get() - n.blob.len() - 32);
+        }
+        Ok(())
+    }
+
+    // 把所有内存中的节点写入磁盘
+    pub fn flush(&self) -> Result<(), TrieError> {
+        self.cap(0)
+    }
+
+    // 内存中节点的数量和字节数
+    pub fn size(&self) -> (usize, usize) {
+        (self.dirties.borrow().len(), self.dirties_size.get())
+    }
+
+    // 节点当前的引用计数, 节点不存在时返回None
+    pub fn refcount(&self, hash: Hash) -> Result<Option<u32>, TrieError> {
+        if let Some(n) = self.dirties.borrow().get(&hash) {
+            return Ok(Some(n.parents));
+        }
+        Ok(self.disk.get(&refcount_key(&hash))?.map(|v| decode_count(&v)))
+    }
+
+    fn incref(&self, hash: Hash) -> Result<(), TrieError> {
+        if let Some(n) = self.dirties.borrow_mut().get_mut(&hash) {
+            n.parents += 1;
+            return Ok(());
+        }
+        // 已经写盘的节点, 更新磁盘上的计数; 不在库中的节点不计数
+        if let Some(v) = self.disk.get(&refcount_key(&hash))? {
+            self.disk.put(&refcount_key(&hash), &(decode_count(&v) + 1).to_be_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn decref(&self, hash: Hash) -> Result<(), TrieError> {
+        let mut dirties = self.dirties.borrow_mut();
+        if let Some(n) = dirties.get_mut(&hash) {
+            if n.parents > 0 {
+                n.parents -= 1;
+            }
+            if n.parents > 0 {
+                return Ok(());
+            }
+            let n = match dirties.remove(&hash) {
+                Some(n) => n,
+                None => return Ok(()),
+            };
+            drop(dirties);
+            self.dirties_size.set(self.dirties_size.get() - n.blob.len() - 32);
+            return self.release(&n.blob);
+        }
+        drop(dirties);
+
+        let count = match self.disk.get(&refcount_key(&hash))? {
+            Some(v) => decode_count(&v),
+            None => return Ok(()),
+        };
+        if count > 1 {
+            return self.disk.put(&refcount_key(&hash), &(count - 1).to_be_bytes());
+        }
+        let blob = self.disk.get(&node_key(&hash))?;
+        self.disk.delete(&node_key(&hash))?;
+        self.disk.delete(&refcount_key(&hash))?;
+        match blob {
+            Some(blob) => self.release(&blob),
+            None => Ok(()),
+        }
+    }
+
+    // 节点被删除后释放它对子节点的引用
+    fn release(&self, blob: &[u8]) -> Result<(), TrieError> {
+        for (_, child) in child_hashes(blob)? {
+            self.decref(child)?;
+        }
+        Ok(())
+    }
+}
+
+impl NodeWriter for HashDB {
+    fn write_nodes(&self, set: &NodeSet) -> Result<(), TrieError> {
+        self.update(set)
+    }
+}
+
+impl NodeReader for HashDB {
+    fn node(&self, _: Hash, _: &[u8], hash: Hash) -> Result<Option<Vec<u8>>, TrieError> {
+        if let Some(n) = self.dirties.borrow().get(&hash) {
+            return Ok(Some(n.blob.clone()));
+        }
+        self.disk.get(&node_key(&hash))
+    }
+}
+
+pub(crate) fn node_key(hash: &Hash) -> Vec<u8> {
+    let mut key = NODE_PREFIX.to_vec();
+    key.extend_from_slice(hash.as_slice());
+    key
+}
+
+pub(crate) fn refcount_key(hash: &Hash) -> Vec<u8> {
+    let mut key = REFCOUNT_PREFIX.to_vec();
+    key.extend_from_slice(hash.as_slice());
+    key
+}
+
+pub(crate) fn decode_count(v: &[u8]) -> u32 {
+    let mut buf = [0_u8; 4];
+    let n = v.len().min(4);
+    buf[4-n..].copy_from_slice(&v[v.len()-n..]);
+    u32::from_be_bytes(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+    use crate::{common::keccak256, kvstore::MemoryStore, Trie, ID};
+
+    // 两个共享大部分节点的版本
+    fn two_versions() -> ((Hash, NodeSet), (Hash, NodeSet)) {
+        let mut t = Trie::new(ID::trie_id(Hash::default()));
+        for i in 0..200u32 {
+            t.try_update(keccak256(&i.to_be_bytes()).to_vec(), Some(vec![i as u8; 1 + (i % 40) as usize])).unwrap();
+        }
+        let v1 = t.commit().unwrap();
+        for i in 0..20u32 {
+            t.try_update(keccak256(&i.to_be_bytes()).to_vec(), Some(vec![0xee; 40])).unwrap();
+        }
+        let v2 = t.commit().unwrap();
+        (v1, v2)
+    }
+
+    fn reachable(db: &HashDB, root: Hash) -> BTreeSet<Hash> {
+        let mut seen = BTreeSet::new();
+        let mut stack = vec![root];
+        while let Some(hash) = stack.pop() {
+            let blob = db.node(Hash::default(), &[], hash).unwrap().unwrap();
+            seen.insert(hash);
+            stack.extend(child_hashes(&blob).unwrap().into_iter().map(|(_, h)| h));
+        }
+        seen
+    }
+
+    // 去掉一个root的引用只删除它独占的节点, 在内存中和写盘后都一样
+    fn check_dereference(flush: bool) {
+        let ((r1, s1), (r2, s2)) = two_versions();
+        let disk = Rc::new(MemoryStore::new());
+        let db = Rc::new(HashDB::new(disk.clone()));
+        db.update(&s1).unwrap();
+        db.reference(r1).unwrap();
+        db.update(&s2).unwrap();
+        db.reference(r2).unwrap();
+        if flush {
+            db.flush().unwrap();
+            assert_eq!(db.size(), (0, 0));
+        }
+        let live = reachable(&db, r2);
+        let owned: Vec<Hash> = reachable(&db, r1).difference(&live).cloned().collect();
+        assert!(!owned.is_empty() && owned.len() < live.len());
+
+        db.dereference(r1).unwrap();
+        for hash in owned.iter() {
+            assert_eq!(db.node(Hash::default(), &[], *hash).unwrap(), None);
+            assert_eq!(db.refcount(*hash).unwrap(), None);
+        }
+        assert_eq!(reachable(&db, r2), live);
+        assert_eq!(db.refcount(r2).unwrap(), Some(1));
+        let t = Trie::open(ID::trie_id(r2), db.clone()).unwrap();
+        assert_eq!(t.get(&keccak256(&3u32.to_be_bytes())[..]).unwrap(), Some(vec![0xee; 40]));
+
+        // 最后一个root也去掉后什么都不剩
+        db.dereference(r2).unwrap();
+        assert_eq!(db.size(), (0, 0));
+        assert!(disk.is_empty());
+    }
+
+    #[test]
+    fn dereference_in_memory() {
+        check_dereference(false);
+    }
+
+    #[test]
+    fn dereference_on_disk() {
+        check_dereference(true);
+    }
+
+    // cap按写入顺序写盘, 子节点先于父节点, 直到内存占用不超过上限
+    #[test]
+    fn cap_flushes_children_first() {
+        let ((r1, s1), _) = two_versions();
+        let disk = Rc::new(MemoryStore::new());
+        let db = HashDB::new(disk.clone());
+        db.update(&s1).unwrap();
+        db.reference(r1).unwrap();
+        let (count, bytes) = db.size();
+        assert_eq!(count, s1.len());
+        db.cap(bytes / 2).unwrap();
+        let (left, size) = db.size();
+        assert!(size <= bytes / 2 && left > 0 && left < count);
+        // 磁盘上的节点引用的子节点都已写盘
+        for (_, n) in s1.iter() {
+            if disk.get(&node_key(&n.hash)).unwrap().is_some() {
+                for (_, child) in child_hashes(&n.blob).unwrap() {
+                    assert!(disk.get(&node_key(&child)).unwrap().is_some());
+                }
+            }
+        }
+        assert_eq!(db.node(Hash::default(), &[], r1).unwrap().map(|b| keccak256(&b)), Some(r1));
+        db.flush().unwrap();
+        assert_eq!(disk.len(), 2 * count);
+        assert_eq!(db.refcount(r1).unwrap(), Some(1));
+    }
+
+    // 删除后重新写入的节点按新的写入位置写盘, 不会先于它的子节点
+    #[test]
+    fn cap_after_readd() {
+        let ((r1, s1), (r2, s2)) = two_versions();
+        let disk = Rc::new(MemoryStore::new());
+        let db = HashDB::new(disk.clone());
+        db.update(&s1).unwrap();
+        db.reference(r1).unwrap();
+        db.flush().unwrap();
+        db.update(&s2).unwrap();
+        db.reference(r2).unwrap();
+        db.dereference(r1).unwrap();
+        db.dereference(r2).unwrap();
+        assert_eq!(db.size(), (0, 0));
+        assert!(disk.is_empty());
+
+        // 第二个版本的完整节点集合, 包括与第一个版本共享的节点
+        let mut t = Trie::new(ID::trie_id(Hash::default()));
+        for i in 0..200u32 {
+            let v = if i < 20 { vec![0xee; 40] } else { vec![i as u8; 1 + (i % 40) as usize] };
+            t.try_update(keccak256(&i.to_be_bytes()).to_vec(), Some(v)).unwrap();
+        }
+        let (root, full) = t.commit().unwrap();
+        assert_eq!(root, r2);
+        db.update(&full).unwrap();
+        db.reference(r2).unwrap();
+        while db.size().0 > 0 {
+            db.cap(db.size().1 - 1).unwrap();
+            for (_, n) in full.iter() {
+                if disk.get(&node_key(&n.hash)).unwrap().is_some() {
+                    for (_, child) in child_hashes(&n.blob).unwrap() {
+                        assert!(disk.get(&node_key(&child)).unwrap().is_some());
+                    }
+                }
+            }
+        }
+        assert_eq!(disk.len(), 2 * full.len());
+    }
+}
@@ -1,22 +1,20 @@
-use std::{rc::Rc, cell::RefCell, io::Write, future, process::Output};
+use std::{rc::Rc, cell::RefCell};
 
-use crypto::{sha2::{Sha256}, digest::Digest, hmac, sha3::Sha3};
-use futures::{Future, executor::block_on};
+use crypto::{digest::Digest, sha3::Sha3};
 
-use crate::{node::{HashNode, Node, NodeType, ShortNode, FullNode, ValueNode}, common, writer::{EncodeBuffer}};
+use crate::{node::{HashNode, Node, NodeType, ShortNode, FullNode, ValueNode}, common, writer::{EncodeBuffer}, TrieError};
 
 pub(crate) struct Hasher {
     hash: Sha3,
-    parallel: bool,
-    out_hash_size: usize,
     w: Rc<RefCell<EncodeBuffer>>,
 }
 
 impl Hasher {
-    pub(crate) fn new(parallel: bool) -> Hasher {
+    // 子节点尚不支持并行计算hash, parallel参数暂不生效
+    pub(crate) fn new(_parallel: bool) -> Hasher {
         // let mut s256 = Sha256::new();
-        let mut s256 = Sha3::keccak256();
-        Hasher { hash: s256, parallel, out_hash_size: s256.output_bytes(), w: Rc::new(RefCell::new(EncodeBuffer::new())) }
+        let s256 = Sha3::keccak256();
+        Hasher { hash: s256, w: Rc::new(RefCell::new(EncodeBuffer::new())) }
     }
 
     pub(crate) fn hash_data(&mut self, data: &[u8]) -> HashNode {
@@ -48,7 +46,7 @@ impl Hasher {
                     cached_node.flags.hash = None;
                 }
 
-                return (hashed, Rc::new(cached_node));
+                (hashed, Rc::new(cached_node))
             },
             NodeType::FullNode => {
                 let f_n = n.into_full_node().unwrap();
@@ -64,10 +62,10 @@ impl Hasher {
                 } else {
                     cached_node.flags.hash = None
                 }
-                return (hashed, Rc::new(cached_node));
+                (hashed, Rc::new(cached_node))
             },
             _ => { // 正常情况不会到此
-                return (Rc::clone(&n), n);
+                (Rc::clone(&n), n)
             }
         }
     }
@@ -97,50 +95,19 @@ impl Hasher {
         Rc::new(hd)
     }
 
-    async fn async_hash_full_node_children(n: &Option<Rc<dyn Node>>, collapsed: Rc<RefCell<FullNode>>, cached: Rc<RefCell<FullNode>>, i: usize) {
-        let mut new_hasher = Hasher::new(false);
-        match n {
-            Some(child_node) => {
-                let (n1, n2) = new_hasher.hash_node(Rc::clone(child_node), false);
-                collapsed.borrow_mut().children[i] = Some(n1);
-                cached.borrow_mut().children[i] = Some(n2);
-            },
-            None => { // 计算hash赋个空valueNode
-                collapsed.borrow_mut().children[i] = Some(Rc::new(ValueNode::default()));
-            }
-        }
-    }
-
     fn hash_full_node_children(&mut self, n: FullNode) -> (FullNode, FullNode) {
         let mut collapsed = n.into_full_node().unwrap();
         let mut cached = n.into_full_node().unwrap();
         
-        if self.parallel && false {
-            // let collapsed = Rc::new(RefCell::new(collapsed));
-            // let cached = Rc::new(RefCell::new(cached));
-
-            // // let arr = [impl Future<Output = ()>;16];
-            // let mut arr = Vec::with_capacity(16);
-            // for (i, _) in [0u8; 16].iter().enumerate() {
-            //     let g = Hasher::async_hash_full_node_children(&n.children[i], 
-            //         Rc::clone(&collapsed), Rc::clone(&cached), i);
-            //     arr.push(g);
-            // }
-            // futures::future::join_all(arr);
-            // let g = collapsed.borrow();
-            // // let f = *g;
-            // return (*g, *cached.borrow());
-        } else {
-            for (i, _) in [0u8; 16].iter().enumerate() {
-                match &n.children[i] {
-                    Some(child_node) => {
-                        let (n1, n2) = self.hash_node(Rc::clone(child_node), false);
-                        collapsed.children[i] = Some(n1);
-                        cached.children[i] = Some(n2);
-                    },
-                    None => { // 计算hash赋个空valueNode
-                        collapsed.children[i] = Some(Rc::new(ValueNode::default()));
-                    }
+        for (i, _) in [0u8; 16].iter().enumerate() {
+            match &n.children[i] {
+                Some(child_node) => {
+                    let (n1, n2) = self.hash_node(Rc::clone(child_node), false);
+                    collapsed.children[i] = Some(n1);
+                    cached.children[i] = Some(n2);
+                },
+                None => { // 计算hash赋个空valueNode
+                    collapsed.children[i] = Some(Rc::new(ValueNode::default()));
                 }
             }
         }
@@ -160,6 +127,22 @@ impl Hasher {
         Rc::new(hd)
     }
 
+    // 节点折叠后的编码, 即存储时的数据
+    pub(crate) fn encode_node(&mut self, n: Rc<dyn Node>) -> Result<Vec<u8>, TrieError> {
+        match n.kind() {
+            NodeType::ShortNode => {
+                let (collapsed, _) = self.hash_short_node_children(n.into_short_node()?);
+                collapsed.encode(Rc::clone(&self.w));
+            },
+            NodeType::FullNode => {
+                let (collapsed, _) = self.hash_full_node_children(n.into_full_node()?);
+                collapsed.encode(Rc::clone(&self.w));
+            },
+            _ => return Err(TrieError::invalid("only short and full nodes can be encoded")),
+        }
+        Ok(self.encod_bytes())
+    }
+
     // 取出buffer中的所有数据
     fn encod_bytes(&self) -> Vec<u8> {
         let ret = self.w.borrow().encode_bytes();
@@ -0,0 +1,226 @@
+use std::rc::Rc;
+
+use crate::{common::{to_hash, Hash}, database::{NodeReader, NodeWriter}, kvstore::KeyValueStore, sync::TrieSync, TrieError};
+
+// 保存修复进度, 重启后继续
+pub const HEAL_PROGRESS_KEY: &[u8] = b"heal-progress";
+// 每轮请求的节点数
+const HEAL_BATCH: usize = 128;
+
+// 按(hex路径, hash)请求节点数据, 可以只返回其中一部分
+pub type NodeFetcher<'a> = dyn FnMut(&[(Vec<u8>, Hash)]) -> Result<Vec<Vec<u8>>, TrieError> + 'a;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HealProgress {
+    // 修复的目标root
+    pub root: Hash,
+    // 收到并校验通过的节点数
+    pub fetched: u64,
+    // 收到的无效或不需要的节点数
+    pub invalid: u64,
+    // 已写入存储的节点数
+    pub committed: u64,
+    pub bytes: u64,
+    // 还未完成的节点数
+    pub pending: u64,
+    pub done: bool,
+}
+
+impl HealProgress {
+    fn new(root: Hash) -> Self {
+        HealProgress { root, fetched: 0, invalid: 0, committed: 0, bytes: 0, pending: 0, done: false }
+    }
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = self.root.to_vec();
+        for v in [self.fetched, self.invalid, self.committed, self.bytes] {
+            buf.extend(v.to_be_bytes());
+        }
+        buf
+    }
+    fn decode(buf: &[u8]) -> Result<Self, TrieError> {
+        if buf.len() != 64 {
+            return Err(TrieError::DecodeError("invalid heal progress".to_string()));
+        }
+        let num = |i: usize| {
+            let mut b = [0_u8; 8];
+            b.copy_from_slice(&buf[32 + i * 8..40 + i * 8]);
+            u64::from_be_bytes(b)
+        };
+        let mut progress = HealProgress::new(to_hash(&buf[..32])?);
+        progress.fetched = num(0);
+        progress.invalid = num(1);
+        progress.committed = num(2);
+        progress.bytes = num(3);
+        Ok(progress)
+    }
+}
+
+// 区间同步后修复trie: 从最新的root开始找出本地缺少或与之不同的节点, 下载直到整个trie一致
+// 只有子树完整的节点才会写入存储, 中断后重新从root遍历, 已修复的子树会直接跳过
+pub struct Healer {
+    owner: Hash,
+    reader: Rc<dyn NodeReader>,
+    writer: Rc<dyn NodeWriter>,
+    // 保存进度的存储
+    store: Rc<dyn KeyValueStore>,
+    progress: HealProgress,
+}
+
+impl Healer {
+    pub fn new(owner: Hash, root: Hash, reader: Rc<dyn NodeReader>, writer: Rc<dyn NodeWriter>, store: Rc<dyn KeyValueStore>) -> Result<Self, TrieError> {
+        // 目标root变化后之前的进度不再有效
+        let progress = match store.get(HEAL_PROGRESS_KEY)? {
+            Some(buf) => {
+                let saved = HealProgress::decode(&buf)?;
+                if saved.root == root { saved } else { HealProgress::new(root) }
+            },
+            None => HealProgress::new(root),
+        };
+        Ok(Healer { owner, reader, writer, store, progress })
+    }
+
+    pub fn progress(&self) -> HealProgress {
+        self.progress
+    }
+
+    // on_progress返回false时中断, 进度已保存, 之后可重新调用继续
+    pub fn heal(&mut self, fetch: &mut NodeFetcher<'_>, on_progress: &mut dyn FnMut(&HealProgress) -> bool) -> Result<HealProgress, TrieError> {
+        let mut sync = TrieSync::new(self.owner, self.progress.root, Rc::clone(&self.reader))?;
+        loop {
+            self.progress.pending = sync.pending() as u64;
+            if sync.is_done() {
+                break;
+            }
+            let reqs = sync.missing(HEAL_BATCH);
+            let mut useful = false;
+            for blob in fetch(&reqs)? {
+                self.progress.bytes += blob.len() as u64;
+                match sync.process(blob) {
+                    Ok(_) => {
+                        self.progress.fetched += 1;
+                        useful = true;
+                    },
+                    Err(TrieError::InvalidNode(_)) | Err(TrieError::DecodeError(_)) => self.progress.invalid += 1,
+                    Err(e) => return Err(e),
+                }
+            }
+            let set = sync.commit();
+            if !set.is_empty() {
+                self.writer.write_nodes(&set)?;
+                self.progress.committed += set.len() as u64;
+            }
+            self.progress.pending = sync.pending() as u64;
+            self.store.put(HEAL_PROGRESS_KEY, &self.progress.encode())?;
+            if !useful {
+                return Err(TrieError::BackendError(format!("no requested node was delivered, {} pending", self.progress.pending)));
+            }
+            if !on_progress(&self.progress) {
+                return Ok(self.progress);
+            }
+        }
+        self.store.delete(HEAL_PROGRESS_KEY)?;
+        self.progress.done = true;
+        on_progress(&self.progress);
+        Ok(self.progress)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{common::keccak256, database::MemoryDB, integrity::verify_integrity, kvstore::MemoryStore, nodeset::NodeSet, Trie, ID};
+
+    fn key(i: u32) -> Vec<u8> {
+        keccak256(&i.to_be_bytes()).to_vec()
+    }
+
+    // 旧版本的trie已在本地, 远端的最新版本修改了一部分key
+    fn setup() -> (Hash, NodeSet, Hash, Rc<MemoryDB>) {
+        let mut t = Trie::new(ID::trie_id(Hash::default()));
+        for i in 0..1000u32 {
+            t.try_update(key(i), Some(vec![i as u8; 1 + (i % 40) as usize])).unwrap();
+        }
+        let (old, old_set) = t.commit().unwrap();
+        let peer = Rc::new(MemoryDB::new());
+        peer.update(&old_set);
+        for i in (0..1000u32).step_by(10) {
+            t.try_update(key(i), Some(vec![0xee; 40])).unwrap();
+        }
+        let (root, set) = t.commit().unwrap();
+        peer.update(&set);
+        (old, old_set, root, peer)
+    }
+
+    fn serve(peer: &MemoryDB, reqs: &[(Vec<u8>, Hash)]) -> Result<Vec<Vec<u8>>, TrieError> {
+        Ok(reqs.iter().filter_map(|(path, hash)| peer.node(Hash::default(), path, *hash).unwrap()).collect())
+    }
+
+    // 只下载与本地不同的节点
+    #[test]
+    fn heals_changed_nodes() {
+        let (_, old_set, root, peer) = setup();
+        let local = Rc::new(MemoryDB::new());
+        local.update(&old_set);
+        let before = local.len();
+        let mut healer = Healer::new(Hash::default(), root, local.clone(), local.clone(), Rc::new(MemoryStore::new())).unwrap();
+        let progress = healer.heal(&mut |reqs| serve(&peer, reqs), &mut |_| true).unwrap();
+        assert!(progress.done && progress.pending == 0);
+        assert_eq!(progress.invalid, 0);
+        assert_eq!(progress.fetched, progress.committed);
+        assert_eq!(local.len() - before, progress.committed as usize);
+        assert!((progress.fetched as usize) < old_set.len() / 2);
+        let report = verify_integrity(local.as_ref(), Hash::default(), root).unwrap();
+        assert!(report.is_ok() && report.leaves == 1000);
+    }
+
+    // 中断后用同一个进度存储继续, 计数延续之前的进度
+    #[test]
+    fn resume_after_interrupt() {
+        let (_, _, root, peer) = setup();
+        let local = Rc::new(MemoryDB::new());
+        let store = Rc::new(MemoryStore::new());
+        let mut healer = Healer::new(Hash::default(), root, local.clone(), local.clone(), store.clone()).unwrap();
+        let first = healer.heal(&mut |reqs| serve(&peer, reqs), &mut |_| false).unwrap();
+        assert!(!first.done && first.fetched > 0 && first.pending > 0);
+        assert!(store.get(HEAL_PROGRESS_KEY).unwrap().is_some());
+
+        // 目标root不同时重新开始
+        let other = Healer::new(Hash::default(), keccak256(b"other"), local.clone(), local.clone(), store.clone()).unwrap();
+        assert_eq!(other.progress().fetched, 0);
+
+        let mut healer = Healer::new(Hash::default(), root, local.clone(), local.clone(), store.clone()).unwrap();
+        assert_eq!(healer.progress().fetched, first.fetched);
+        assert_eq!(healer.progress().bytes, first.bytes);
+        let mut rounds = 0;
+        let done = healer.heal(&mut |reqs| serve(&peer, reqs), &mut |_| { rounds += 1; true }).unwrap();
+        assert!(done.done && rounds > 1);
+        assert!(done.fetched > first.fetched);
+        assert!(store.get(HEAL_PROGRESS_KEY).unwrap().is_none());
+        let report = verify_integrity(local.as_ref(), Hash::default(), root).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(local.len() as u64, report.nodes);
+    }
+
+    #[test]
+    fn invalid_and_missing_data() {
+        let (_, _, root, peer) = setup();
+        let local = Rc::new(MemoryDB::new());
+        let mut healer = Healer::new(Hash::default(), root, local.clone(), local.clone(), Rc::new(MemoryStore::new())).unwrap();
+        // 混入无关数据的应答仍可推进, 无关数据计为无效
+        let mut junk = true;
+        let progress = healer.heal(&mut |reqs| {
+            let mut blobs = serve(&peer, reqs)?;
+            if junk {
+                blobs.push(vec![0xc2, 0x80, 0x80]);
+                junk = false;
+            }
+            Ok(blobs)
+        }, &mut |_| true).unwrap();
+        assert!(progress.done);
+        assert_eq!(progress.invalid, 1);
+
+        let local = Rc::new(MemoryDB::new());
+        let mut healer = Healer::new(Hash::default(), root, local.clone(), local, Rc::new(MemoryStore::new())).unwrap();
+        assert!(matches!(healer.heal(&mut |_| Ok(Vec::new()), &mut |_| true), Err(TrieError::BackendError(_))));
+    }
+}
@@ -0,0 +1,212 @@
+use std::rc::Rc;
+
+use crate::{common::{keccak256, Hash}, database::NodeReader, node::{decode_node, HashNode, Node, NodeType}, TrieError};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IssueKind {
+    // 节点不存在
+    Missing,
+    // 节点数据的hash与存储它的key不一致
+    HashMismatch(Hash),
+    // 节点数据无法解码
+    Undecodable(String),
+    // fullNode只有一个子节点, 应该合并为shortNode
+    SingleChildFullNode,
+    // shortNode的子节点也是shortNode, 应该合并
+    NestedShortNode,
+    // shortNode的key为空
+    EmptyKey,
+    // 编码小于32字节的非root节点应该内嵌在父节点中
+    UnembeddedNode,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IntegrityIssue {
+    // 节点的hex路径
+    pub path: Vec<u8>,
+    // 所在存储节点的hash
+    pub hash: Hash,
+    pub kind: IssueKind,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IntegrityReport {
+    // 检查过的存储节点数
+    pub nodes: u64,
+    // 叶子(值)的数量
+    pub leaves: u64,
+    // 存储节点的总字节数
+    pub bytes: u64,
+    pub issues: Vec<IntegrityIssue>,
+}
+
+impl IntegrityReport {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+    pub fn count(&self, f: impl Fn(&IssueKind) -> bool) -> usize {
+        self.issues.iter().filter(|i| f(&i.kind)).count()
+    }
+    pub fn missing(&self) -> usize {
+        self.count(|k| *k == IssueKind::Missing)
+    }
+    pub fn corrupted(&self) -> usize {
+        self.count(|k| matches!(k, IssueKind::HashMismatch(_) | IssueKind::Undecodable(_)))
+    }
+    pub fn non_canonical(&self) -> usize {
+        self.count(|k| matches!(k, IssueKind::SingleChildFullNode | IssueKind::NestedShortNode | IssueKind::EmptyKey | IssueKind::UnembeddedNode))
+    }
+}
+
+// 从root遍历所有可达节点, 重新计算hash并检查节点结构
+pub fn verify_integrity(reader: &dyn NodeReader, owner: Hash, root: Hash) -> Result<IntegrityReport, TrieError> {
+    let mut report = IntegrityReport::default();
+    if root.is_empty_root() {
+        return Ok(report);
+    }
+    // (hash, 路径, 父节点是否为shortNode)
+    let mut stack = vec![(root, Vec::new(), false)];
+    while let Some((hash, path, parent_short)) = stack.pop() {
+        // 读取原始数据自己计算hash, 按路径存储时hash不符的数据才能报告为损坏而不是缺失
+        let blob = match reader.unchecked_node(owner, &path, hash)? {
+            Some(blob) => blob,
+            None => {
+                report.issues.push(IntegrityIssue { path, hash, kind: IssueKind::Missing });
+                continue;
+            },
+        };
+        report.nodes += 1;
+        report.bytes += blob.len() as u64;
+        let actual = keccak256(&blob);
+        if actual != hash {
+            report.issues.push(IntegrityIssue { path: path.clone(), hash, kind: IssueKind::HashMismatch(actual) });
+        }
+        if blob.len() < 32 && !path.is_empty() {
+            report.issues.push(IntegrityIssue { path: path.clone(), hash, kind: IssueKind::UnembeddedNode });
+        }
+        let n = match decode_node(Some(HashNode::from(*hash)), &blob) {
+            Ok(n) => n,
+            Err(e) => {
+                report.issues.push(IntegrityIssue { path, hash, kind: IssueKind::Undecodable(e.to_string()) });
+                continue;
+            },
+        };
+        check_node(&n, hash, path, parent_short, &mut report, &mut stack)?;
+    }
+    Ok(report)
+}
+
+fn check_node(n: &Rc<dyn Node>, hash: Hash, path: Vec<u8>, parent_short: bool, report: &mut IntegrityReport, stack: &mut Vec<(Hash, Vec<u8>, bool)>) -> Result<(), TrieError> {
+    match n.kind() {
+        NodeType::HashNode => {
+            stack.push((Hash::from(n.into_hash_node()?.0), path, parent_short));
+        },
+        NodeType::ValueNode => report.leaves += 1,
+        NodeType::ShortNode => {
+            let sn = n.into_short_node()?;
+            if parent_short {
+                report.issues.push(IntegrityIssue { path: path.clone(), hash, kind: IssueKind::NestedShortNode });
+            }
+            if sn.key.is_empty() {
+                report.issues.push(IntegrityIssue { path: path.clone(), hash, kind: IssueKind::EmptyKey });
+            }
+            let mut child_path = path;
+            child_path.extend(&sn.key);
+            check_node(&sn.val, hash, child_path, true, report, stack)?;
+        },
+        NodeType::FullNode => {
+            let f_n = n.into_full_node()?;
+            if f_n.children.iter().flatten().count() < 2 {
+                report.issues.push(IntegrityIssue { path: path.clone(), hash, kind: IssueKind::SingleChildFullNode });
+            }
+            for (i, child) in f_n.children.iter().enumerate() {
+                if let Some(child) = child {
+                    let mut child_path = path.clone();
+                    child_path.push(i as u8);
+                    check_node(child, hash, child_path, false, report, stack)?;
+                }
+            }
+        },
+        NodeType::NullNode => {},
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::MemoryDB, kvstore::{KeyValueStore, MemoryStore}, nodeset::NodeSet, pathdb::{self, LayerTree, PathDB}, Trie, ID};
+
+    fn build() -> (Hash, NodeSet) {
+        let mut t = Trie::new(ID::trie_id(Hash::default()));
+        for i in 0..200u32 {
+            t.try_update(keccak256(&i.to_be_bytes()).to_vec(), Some(vec![i as u8; 1 + (i % 40) as usize])).unwrap();
+        }
+        t.commit().unwrap()
+    }
+
+    // 选一个单独存储的叶子节点, 改动值的末尾字节后仍能解码
+    fn victim(set: &NodeSet) -> (Vec<u8>, Hash, Vec<u8>) {
+        let (path, n) = set.iter().find(|(_, n)| {
+            match decode_node(None, &n.blob) {
+                Ok(n) => n.kind() == NodeType::ShortNode && n.into_short_node().unwrap().val.kind() == NodeType::ValueNode,
+                Err(_) => false,
+            }
+        }).unwrap();
+        (path.clone(), n.hash, n.blob.clone())
+    }
+
+    #[test]
+    fn clean_trie() {
+        let (root, set) = build();
+        let db = MemoryDB::new();
+        db.update(&set);
+        let report = verify_integrity(&db, Hash::default(), root).unwrap();
+        assert!(report.is_ok(), "{:?}", report.issues);
+        assert_eq!(report.leaves, 200);
+        assert_eq!(report.nodes as usize, set.len());
+    }
+
+    // 按路径存储时, 路径上的数据被改坏应报告为hash不符, 而不是缺失
+    #[test]
+    fn pathdb_corruption() {
+        let (root, set) = build();
+        let disk = Rc::new(MemoryStore::new());
+        let db = PathDB::new(disk.clone());
+        db.commit(Hash::empty_root_hash(), root, &[set]).unwrap();
+        let (_, set) = build();
+        let (path, hash, mut blob) = victim(&set);
+        let last = blob.len() - 1;
+        blob[last] ^= 0x01;
+        disk.put(&pathdb::node_key(&Hash::default(), &path), &blob).unwrap();
+
+        let report = verify_integrity(&db, Hash::default(), root).unwrap();
+        assert_eq!(report.missing(), 0);
+        assert_eq!(report.issues, vec![IntegrityIssue { path: path.clone(), hash, kind: IssueKind::HashMismatch(keccak256(&blob)) }]);
+
+        // 经过差异层读取时同样能发现
+        let tree = LayerTree::new(Rc::new(PathDB::new(disk.clone())), 4);
+        let report = verify_integrity(&tree.reader(root).unwrap(), Hash::default(), root).unwrap();
+        assert_eq!(report.corrupted(), 1);
+        assert_eq!(report.missing(), 0);
+
+        // 删除后才是缺失
+        disk.delete(&pathdb::node_key(&Hash::default(), &path)).unwrap();
+        let report = verify_integrity(&db, Hash::default(), root).unwrap();
+        assert_eq!(report.issues, vec![IntegrityIssue { path, hash, kind: IssueKind::Missing }]);
+    }
+
+    #[test]
+    fn hashdb_corruption() {
+        let (root, set) = build();
+        let (_, hash, mut blob) = victim(&set);
+        let db = MemoryDB::new();
+        db.update(&set);
+        let last = blob.len() - 1;
+        blob[last] ^= 0x01;
+        db.insert(hash, blob.clone());
+        let report = verify_integrity(&db, Hash::default(), root).unwrap();
+        assert_eq!(report.corrupted(), 1);
+        assert_eq!(report.issues[0].kind, IssueKind::HashMismatch(keccak256(&blob)));
+    }
+}
@@ -0,0 +1,150 @@
+use std::rc::Rc;
+
+use crate::{common::{Hash, hex_to_key}, node::{Node, NodeType}, range::{overlap, Overlap}, resolver::Resolver, TrieError};
+
+// 按key顺序遍历trie中的所有键值对
+pub struct TrieIterator {
+    // 待访问的节点及其hex路径, 栈顶为下一个访问的节点
+    stack: Vec<(Rc<dyn Node>, Vec<u8>)>,
+    resolver: Resolver,
+    // 起始key的hex路径(不含终止符), 小于它的子树直接跳过
+    start: Vec<u8>,
+}
+
+impl TrieIterator {
+    pub(crate) fn new(root: Rc<dyn Node>, resolver: Resolver) -> Self {
+        TrieIterator::with_start(root, resolver, Vec::new())
+    }
+    pub(crate) fn with_start(root: Rc<dyn Node>, resolver: Resolver, start: Vec<u8>) -> Self {
+        TrieIterator { stack: vec![(root, Vec::new())], resolver, start }
+    }
+    fn push(&mut self, n: Rc<dyn Node>, path: Vec<u8>) {
+        let key_path = path.strip_suffix(&[16]).unwrap_or(&path);
+        if overlap(key_path, &self.start, None) != Overlap::Outside {
+            self.stack.push((n, path));
+        }
+    }
+}
+
+impl Iterator for TrieIterator {
+    type Item = Result<(Vec<u8>, Vec<u8>), TrieError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((n, path)) = self.stack.pop() {
+            match n.kind() {
+                NodeType::NullNode => {},
+                NodeType::ValueNode => {
+                    // 值的key就是路径, 路径是起始key的前缀时仍可能小于起始key
+                    if path.strip_suffix(&[16]).unwrap_or(&path) < self.start.as_slice() {
+                        continue;
+                    }
+                    return match n.into_value_node() {
+                        Ok(vn) => Some(Ok((hex_to_key(&path), vn.0))),
+                        Err(e) => Some(Err(e)),
+                    };
+                },
+                NodeType::ShortNode => {
+                    let sn = match n.into_short_node() {
+                        Ok(sn) => sn,
+                        Err(e) => return Some(Err(e)),
+                    };
+                    let mut next_path = path;
+                    next_path.extend(&sn.key);
+                    self.push(sn.val, next_path);
+                },
+                NodeType::FullNode => {
+                    let f_n = match n.into_full_node() {
+                        Ok(f_n) => f_n,
+                        Err(e) => return Some(Err(e)),
+                    };
+                    // 倒序入栈, 终止符位置(16)的值对应的key最短, 最先访问
+                    for i in (0..16).rev() {
+                        if let Some(child) = &f_n.children[i] {
+                            let mut next_path = path.clone();
+                            next_path.push(i as u8);
+                            self.push(Rc::clone(child), next_path);
+                        }
+                    }
+                    if let Some(child) = &f_n.children[16] {
+                        let mut next_path = path;
+                        next_path.push(16);
+                        self.push(Rc::clone(child), next_path);
+                    }
+                },
+                NodeType::HashNode => {
+                    let resolved = n.into_hash_node().and_then(|hn| self.resolver.resolve(Hash::from(hn.0), &path));
+                    match resolved {
+                        Ok(rn) => self.stack.push((rn, path)),
+                        Err(e) => {
+                            // 节点无法加载, 结束遍历
+                            self.stack.clear();
+                            return Some(Err(e));
+                        },
+                    }
+                },
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::{common::keccak256, database::MemoryDB, Trie, ID};
+
+    // 长度不同、互为前缀的key按字节序输出, 与BTreeMap的顺序一致
+    fn sample() -> (Trie, BTreeMap<Vec<u8>, Vec<u8>>) {
+        let mut t = Trie::new(ID::trie_id(Hash::default()));
+        let mut expected = BTreeMap::new();
+        for i in 0..300u32 {
+            let mut k = keccak256(&i.to_be_bytes()).to_vec();
+            k.truncate(1 + (i % 5) as usize);
+            let v = vec![i as u8; 1 + (i % 40) as usize];
+            t.try_update(k.clone(), Some(v.clone())).unwrap();
+            expected.insert(k, v);
+        }
+        (t, expected)
+    }
+
+    #[test]
+    fn ordered() {
+        let (t, expected) = sample();
+        let items: Vec<(Vec<u8>, Vec<u8>)> = t.iter().collect::<Result<_, _>>().unwrap();
+        assert_eq!(items, expected.into_iter().collect::<Vec<_>>());
+    }
+
+    // 从存储打开的trie在遍历时按需加载节点
+    #[test]
+    fn resolves_from_storage() {
+        let (mut t, expected) = sample();
+        let (root, set) = t.commit().unwrap();
+        let db = Rc::new(MemoryDB::new());
+        db.update(&set);
+        let t = Trie::open(ID::trie_id(root), db).unwrap();
+        let items: Vec<(Vec<u8>, Vec<u8>)> = t.iter().collect::<Result<_, _>>().unwrap();
+        assert_eq!(items, expected.clone().into_iter().collect::<Vec<_>>());
+
+        let start = expected.keys().nth(100).unwrap().clone();
+        let from: Vec<Vec<u8>> = t.iter_from(&start).map(|kv| kv.unwrap().0).collect();
+        assert_eq!(from, expected.range(start..).map(|(k, _)| k.clone()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn missing_node() {
+        let (mut t, _) = sample();
+        let (root, set) = t.commit().unwrap();
+        let (path, n) = set.iter().find(|(path, _)| !path.is_empty()).unwrap();
+        let db = Rc::new(MemoryDB::new());
+        for (p, n) in set.iter() {
+            if p != path {
+                db.insert(n.hash, n.blob.clone());
+            }
+        }
+        let t = Trie::open(ID::trie_id(root), db).unwrap();
+        let err = t.iter().find_map(|kv| kv.err()).unwrap();
+        assert_eq!(err, TrieError::missing(path, n.hash));
+    }
+}
@@ -0,0 +1,365 @@
+use crate::TrieError;
+
+// 证明输入输出用到的最小JSON, 数字保留原始文本, 不做精度转换
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    Num(String),
+    Str(String),
+    Arr(Vec<Json>),
+    Obj(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub(crate) fn get(&self, key: &str) -> Result<&Json, TrieError> {
+        match self {
+            Json::Obj(fields) => match fields.iter().find(|(k, _)| k == key) {
+                Some((_, v)) => Ok(v),
+                None => Err(json_err(&format!("missing field {}", key))),
+            },
+            _ => Err(json_err("expected object")),
+        }
+    }
+    pub(crate) fn as_str(&self) -> Result<&str, TrieError> {
+        match self {
+            Json::Str(s) => Ok(s),
+            _ => Err(json_err("expected string")),
+        }
+    }
+    pub(crate) fn as_arr(&self) -> Result<&[Json], TrieError> {
+        match self {
+            Json::Arr(a) => Ok(a),
+            _ => Err(json_err("expected array")),
+        }
+    }
+
+    pub(crate) fn encode(&self) -> String {
+        let mut out = String::new();
+        self.write(&mut out);
+        out
+    }
+    fn write(&self, out: &mut String) {
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+            Json::Num(n) => out.push_str(n),
+            Json::Str(s) => {
+                out.push('"');
+                for c in s.chars() {
+                    match c {
+                        '"' => out.push_str("\\\""),
+                        '\\' => out.push_str("\\\\"),
+                        c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+                        c => out.push(c),
+                    }
+                }
+                out.push('"');
+            },
+            Json::Arr(a) => {
+                out.push('[');
+                for (i, v) in a.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    v.write(out);
+                }
+                out.push(']');
+            },
+            Json::Obj(fields) => {
+                out.push('{');
+                for (i, (k, v)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    Json::Str(k.clone()).write(out);
+                    out.push(':');
+                    v.write(out);
+                }
+                out.push('}');
+            },
+        }
+    }
+
+    pub(crate) fn parse(s: &str) -> Result<Json, TrieError> {
+        let mut p = Parser { buf: s.as_bytes(), pos: 0, depth: 0 };
+        let v = p.value()?;
+        p.skip_ws();
+        if p.pos != p.buf.len() {
+            return Err(json_err("trailing characters"));
+        }
+        Ok(v)
+    }
+}
+
+fn json_err(v: &str) -> TrieError {
+    TrieError::DecodeError(format!("json: {}", v))
+}
+
+// 数组和对象最多嵌套的层数, 防止恶意输入导致栈溢出
+const MAX_DEPTH: usize = 64;
+
+struct Parser<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn skip_ws(&mut self) {
+        while self.pos < self.buf.len() && self.buf[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+    fn next(&mut self) -> Result<u8, TrieError> {
+        self.skip_ws();
+        match self.buf.get(self.pos) {
+            Some(b) => {
+                self.pos += 1;
+                Ok(*b)
+            },
+            None => Err(json_err("unexpected end of input")),
+        }
+    }
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_ws();
+        self.buf.get(self.pos).copied()
+    }
+    fn value(&mut self) -> Result<Json, TrieError> {
+        match self.next()? {
+            b'"' => Ok(Json::Str(self.string()?)),
+            b @ (b'[' | b'{') => {
+                if self.depth >= MAX_DEPTH {
+                    return Err(json_err("nesting too deep"));
+                }
+                self.depth += 1;
+                let v = if b == b'[' { self.array() } else { self.object() };
+                self.depth -= 1;
+                v
+            },
+            b't' => self.literal(b"rue", Json::Bool(true)),
+            b'f' => self.literal(b"alse", Json::Bool(false)),
+            b'n' => self.literal(b"ull", Json::Null),
+            b'-' | b'0'..=b'9' => {
+                self.pos -= 1;
+                self.number()
+            },
+            _ => Err(json_err("unexpected character")),
+        }
+    }
+    fn array(&mut self) -> Result<Json, TrieError> {
+        let mut a = Vec::new();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Json::Arr(a));
+        }
+        loop {
+            a.push(self.value()?);
+            match self.next()? {
+                b',' => continue,
+                b']' => return Ok(Json::Arr(a)),
+                _ => return Err(json_err("expected , or ]")),
+            }
+        }
+    }
+    fn object(&mut self) -> Result<Json, TrieError> {
+        let mut fields = Vec::new();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Json::Obj(fields));
+        }
+        loop {
+            if self.next()? != b'"' {
+                return Err(json_err("expected field name"));
+            }
+            let key = self.string()?;
+            if self.next()? != b':' {
+                return Err(json_err("expected :"));
+            }
+            fields.push((key, self.value()?));
+            match self.next()? {
+                b',' => continue,
+                b'}' => return Ok(Json::Obj(fields)),
+                _ => return Err(json_err("expected , or }")),
+            }
+        }
+    }
+    // 读取true/false/null剩余的部分
+    fn literal(&mut self, rest: &[u8], v: Json) -> Result<Json, TrieError> {
+        if !self.buf[self.pos..].starts_with(rest) {
+            return Err(json_err("invalid literal"));
+        }
+        self.pos += rest.len();
+        Ok(v)
+    }
+    // -?(0|[1-9][0-9]*)(.[0-9]+)?([eE][+-]?[0-9]+)?
+    fn number(&mut self) -> Result<Json, TrieError> {
+        let start = self.pos;
+        if self.buf[self.pos] == b'-' {
+            self.pos += 1;
+        }
+        match self.buf.get(self.pos) {
+            Some(b'0') => self.pos += 1,
+            Some(b'1'..=b'9') => {
+                self.digits();
+            },
+            _ => return Err(json_err("invalid number")),
+        }
+        if self.buf.get(self.pos) == Some(&b'.') {
+            self.pos += 1;
+            if !self.digits() {
+                return Err(json_err("invalid number"));
+            }
+        }
+        if let Some(b'e' | b'E') = self.buf.get(self.pos) {
+            self.pos += 1;
+            if let Some(b'+' | b'-') = self.buf.get(self.pos) {
+                self.pos += 1;
+            }
+            if !self.digits() {
+                return Err(json_err("invalid number"));
+            }
+        }
+        Ok(Json::Num(String::from_utf8_lossy(&self.buf[start..self.pos]).to_string()))
+    }
+    // 跳过连续的数字, 返回是否至少有一个
+    fn digits(&mut self) -> bool {
+        let start = self.pos;
+        while let Some(b'0'..=b'9') = self.buf.get(self.pos) {
+            self.pos += 1;
+        }
+        self.pos > start
+    }
+    // 读取字符串, 起始的引号已读过
+    fn string(&mut self) -> Result<String, TrieError> {
+        let mut out = Vec::new();
+        loop {
+            let b = match self.buf.get(self.pos) {
+                Some(b) => *b,
+                None => return Err(json_err("unterminated string")),
+            };
+            self.pos += 1;
+            match b {
+                b'"' => break,
+                b'\\' => {
+                    let e = match self.buf.get(self.pos) {
+                        Some(e) => *e,
+                        None => return Err(json_err("unterminated string")),
+                    };
+                    self.pos += 1;
+                    match e {
+                        b'"' | b'\\' | b'/' => out.push(e),
+                        b'n' => out.push(b'\n'),
+                        b't' => out.push(b'\t'),
+                        b'r' => out.push(b'\r'),
+                        b'b' => out.push(0x08),
+                        b'f' => out.push(0x0c),
+                        b'u' => {
+                            let c = self.unicode_escape()?;
+                            out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                        },
+                        _ => return Err(json_err("unsupported escape")),
+                    }
+                },
+                b => out.push(b),
+            }
+        }
+        String::from_utf8(out).map_err(|_| json_err("invalid utf-8"))
+    }
+    // \u后的4位hex, 代理对需要连续两个转义
+    fn unicode_escape(&mut self) -> Result<char, TrieError> {
+        let hi = self.hex4()?;
+        let code = if (0xd800..0xdc00).contains(&hi) {
+            if !self.buf[self.pos..].starts_with(b"\\u") {
+                return Err(json_err("unpaired surrogate"));
+            }
+            self.pos += 2;
+            let lo = self.hex4()?;
+            if !(0xdc00..0xe000).contains(&lo) {
+                return Err(json_err("unpaired surrogate"));
+            }
+            0x10000 + ((hi - 0xd800) << 10) + (lo - 0xdc00)
+        } else {
+            hi
+        };
+        char::from_u32(code).ok_or_else(|| json_err("invalid unicode escape"))
+    }
+    fn hex4(&mut self) -> Result<u32, TrieError> {
+        let s = match self.buf.get(self.pos..self.pos + 4) {
+            Some(s) => std::str::from_utf8(s).map_err(|_| json_err("invalid unicode escape"))?,
+            None => return Err(json_err("invalid unicode escape")),
+        };
+        self.pos += 4;
+        u32::from_str_radix(s, 16).map_err(|_| json_err("invalid unicode escape"))
+    }
+}
+
+// 0x前缀的hex字符串
+pub(crate) fn to_hex(data: &[u8]) -> String {
+    format!("0x{}", hex::encode(data))
+}
+
+// 解析0x前缀的hex, 数量类型的值允许奇数长度
+pub(crate) fn from_hex(s: &str) -> Result<Vec<u8>, TrieError> {
+    let s = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).unwrap_or(s);
+    let padded = if s.len() % 2 == 1 { format!("0{}", s) } else { s.to_string() };
+    hex::decode(padded).map_err(|e| json_err(&format!("invalid hex: {}", e)))
+}
+
+// RPC数量格式, 没有前导0, 0写成0x0
+pub(crate) fn to_quantity(data: &[u8]) -> String {
+    let s = hex::encode(data);
+    let s = s.trim_start_matches('0');
+    if s.is_empty() {
+        "0x0".to_string()
+    } else {
+        format!("0x{}", s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_all_types() {
+        let v = Json::parse(" {\"id\": -1.5e3, \"ok\": true, \"no\": false, \"x\": null, \"a\": [0, 12, \"\\u00e9\\ud83d\\ude00\"]} ").unwrap();
+        assert_eq!(v.get("id").unwrap(), &Json::Num("-1.5e3".to_string()));
+        assert_eq!(v.get("ok").unwrap(), &Json::Bool(true));
+        assert_eq!(v.get("no").unwrap(), &Json::Bool(false));
+        assert_eq!(v.get("x").unwrap(), &Json::Null);
+        let a = v.get("a").unwrap().as_arr().unwrap();
+        assert_eq!(a[1], Json::Num("12".to_string()));
+        assert_eq!(a[2].as_str().unwrap(), "\u{e9}\u{1f600}");
+        assert_eq!(v.encode(), "{\"id\":-1.5e3,\"ok\":true,\"no\":false,\"x\":null,\"a\":[0,12,\"\u{e9}\u{1f600}\"]}");
+    }
+
+    #[test]
+    fn rejects_invalid() {
+        for s in ["01", "-", "1.", "1e", "tru", "nul", "[1,]", "{\"a\" 1}", "\"\\ud83d\"", "1 2"] {
+            assert!(Json::parse(s).is_err(), "{}", s);
+        }
+    }
+
+    // 嵌套过深的输入返回错误而不是栈溢出
+    #[test]
+    fn nesting_limit() {
+        let nested = |n: usize, open: &str, close: &str| format!("{}1{}", open.repeat(n), close.repeat(n));
+        assert!(Json::parse(&nested(MAX_DEPTH, "[", "]")).is_ok());
+        assert!(Json::parse(&nested(MAX_DEPTH, "{\"a\":", "}")).is_ok());
+        assert!(Json::parse(&nested(MAX_DEPTH + 1, "[", "]")).is_err());
+        assert!(Json::parse(&nested(MAX_DEPTH + 1, "{\"a\":", "}")).is_err());
+        assert!(Json::parse(&nested(1_000_000, "[", "]")).is_err());
+        assert!(Json::parse(&"[{\"a\":".repeat(500_000)).is_err());
+    }
+
+    #[test]
+    fn hex_helpers() {
+        assert_eq!(from_hex("0x1").unwrap(), vec![1]);
+        assert_eq!(from_hex("0xabcd").unwrap(), vec![0xab, 0xcd]);
+        assert!(from_hex("0xzz").is_err());
+        assert_eq!(to_quantity(&[0, 0]), "0x0");
+        assert_eq!(to_quantity(&[0, 1, 0]), "0x100");
+        assert_eq!(to_hex(&[0, 1]), "0x0001");
+    }
+}
@@ -0,0 +1,60 @@
+use std::{cell::RefCell, collections::BTreeMap, ops::Bound};
+
+use crate::TrieError;
+
+pub type KeyValue = (Vec<u8>, Vec<u8>);
+
+// 磁盘键值存储接口
+pub trait KeyValueStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, TrieError>;
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<(), TrieError>;
+    fn delete(&self, key: &[u8]) -> Result<(), TrieError>;
+    // 按key顺序返回从start(含)开始的最多limit个键值对
+    fn scan(&self, start: &[u8], limit: usize) -> Result<Vec<KeyValue>, TrieError>;
+}
+
+// 内存实现, 用于测试或作为本地存储的替身
+pub struct MemoryStore {
+    data: RefCell<BTreeMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore { data: RefCell::new(BTreeMap::new()) }
+    }
+    pub fn len(&self) -> usize {
+        self.data.borrow().len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.data.borrow().is_empty()
+    }
+    // 所有键值占用的字节数
+    pub fn size(&self) -> usize {
+        self.data.borrow().iter().map(|(k, v)| k.len() + v.len()).sum()
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KeyValueStore for MemoryStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, TrieError> {
+        Ok(self.data.borrow().get(key).cloned())
+    }
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<(), TrieError> {
+        self.data.borrow_mut().insert(key.to_vec(), value.to_vec());
+        Ok(())
+    }
+    fn delete(&self, key: &[u8]) -> Result<(), TrieError> {
+        self.data.borrow_mut().remove(key);
+        Ok(())
+    }
+    fn scan(&self, start: &[u8], limit: usize) -> Result<Vec<KeyValue>, TrieError> {
+        let data = self.data.borrow();
+        let range = data.range::<[u8], _>((Bound::Included(start), Bound::Unbounded));
+        Ok(range.take(limit).map(|(k, v)| (k.clone(), v.clone())).collect())
+    }
+}
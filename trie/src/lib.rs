@@ -1,9 +1,32 @@
-use std::{rc::Rc, cell::RefCell, fmt, error::Error};
+use std::{rc::Rc, cell::RefCell};
 
-use  common::{Hash, key_to_hex, prefix_len};
+use  common::{Hash, keccak256, key_to_hex, prefix_len};
 use node::{Node, NodeType, NilNode, ValueNode, FullNode, HashNode, ShortNode};
 
 use crate::hasher::Hasher;
+use crate::committer::Committer;
+use crate::resolver::Resolver;
+pub use crate::asyncreader::AsyncNodeReader;
+pub use crate::cache::{NodeCache, CacheStats, DEFAULT_CACHE_SIZE};
+pub use crate::database::{NodeReader, NodeWriter, MemoryDB};
+pub use crate::flatdb::{FlatStore, FlatIterator};
+pub use crate::hashdb::HashDB;
+pub use crate::heal::{Healer, HealProgress, NodeFetcher};
+pub use crate::integrity::{verify_integrity, IntegrityReport, IntegrityIssue, IssueKind};
+pub use crate::kvstore::{KeyValue, KeyValueStore, MemoryStore};
+pub use crate::pathdb::{PathDB, StateHistory, StateMeta, LayerTree, LayerReader};
+pub use crate::proof::{MultiProof, ProofFormat, verify_proof, verify_multiproof, encode_proof, decode_proof};
+pub use crate::range::{verify_gap, RangeResponse, serve_range, verify_range, consume_range};
+pub use crate::prefetch::Prefetcher;
+pub use crate::pruner::{Pruner, PruneProgress, PrunePhase};
+pub use crate::error::TrieError;
+pub use crate::nodeset::{NodeSet, TrieNode};
+pub use crate::iterator::TrieIterator;
+pub use crate::snapshot::TrieSnapshot;
+pub use crate::state::{Address, U256, StateAccount, StateDB, AccountProof, StorageProof, verify_account_proof};
+pub use crate::stacktrie::StackTrie;
+pub use crate::sync::TrieSync;
+pub use crate::witness::Witness;
 
 pub struct ID {
     state_root: Hash,
@@ -13,32 +36,27 @@ pub struct ID {
 
 impl ID {
     pub fn state_trie_id(root: Hash) -> Self {
-        ID { state_root: root, owner: Hash::default(), root: Hash::default() }
+        ID { state_root: root, owner: Hash::default(), root }
     }
     pub fn trie_id(root: Hash) -> Self {
-        ID { state_root: root, owner: Hash::default(), root: Hash::default() }
+        ID { state_root: root, owner: Hash::default(), root }
     }
     pub fn storage_trie_id(state_root: Hash, owner: Hash, root: Hash) -> Self {
-        ID { state_root: state_root, owner: owner, root: root }
+        ID { state_root, owner, root }
     }
-}
-
-#[derive(Debug,Clone)]
-pub struct NodeError(String);
-impl fmt::Display for NodeError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.0)
+    pub fn state_root(&self) -> Hash {
+        self.state_root
     }
-}
-impl Error for NodeError {}
-
-impl NodeError {
-    fn from(v: &str) -> Self {
-        NodeError(v.to_string())
+    pub fn owner(&self) -> Hash {
+        self.owner
+    }
+    pub fn root(&self) -> Hash {
+        self.root
     }
 }
 
 
+
 pub struct Trie {
     // root: T::MyType,
     // root: Rc<RefCell<dyn Node>>,
@@ -51,14 +69,43 @@ pub struct Trie {
     // root: T,
     owner: Hash,
 
-    unhashed: u64
+    unhashed: u64,
+    // 检查点日志, 每项保存创建检查点时的root和unhashed
+    journal: Vec<(Rc<dyn Node>, u64)>,
+    // 从存储中加载节点
+    resolver: Resolver,
 }
 
 impl Trie {
     pub fn new(id: ID) -> Self {
         // Trie { root: Rc::new(RefCell::new(NilNode)), owner: id.owner, unhashed: 0, root_full_node: None, root_short_node: None, root_hash_node: None, root_value_node: None }
         // Trie { root: Rc::new(NilNode), owner: id.owner, unhashed: 0, root_full_node: None, root_short_node: None, root_hash_node: None, root_value_node: None }
-        Trie { root: Rc::new(NilNode), owner: id.owner, unhashed: 0 }
+        Trie { root: Rc::new(NilNode), owner: id.owner, unhashed: 0, journal: Vec::new(), resolver: Resolver::new(id.owner, None) }
+    }
+    // 从存储中打开id.root对应的trie, 节点在访问时按需加载
+    pub fn open(id: ID, reader: Rc<dyn NodeReader>) -> Result<Self, TrieError> {
+        Trie::open_with_cache(id, reader, Rc::new(RefCell::new(NodeCache::new(DEFAULT_CACHE_SIZE))))
+    }
+    // 使用指定的节点缓存打开trie, 多个trie可共享同一个缓存
+    pub fn open_with_cache(id: ID, reader: Rc<dyn NodeReader>, cache: Rc<RefCell<NodeCache>>) -> Result<Self, TrieError> {
+        let resolver = Resolver::with_cache(id.owner, Some(reader), cache);
+        let root: Rc<dyn Node> = if id.root.is_empty_root() {
+            Rc::new(NilNode)
+        } else {
+            resolver.resolve(id.root, &[])?
+        };
+        Ok(Trie { root, owner: id.owner, unhashed: 0, journal: Vec::new(), resolver })
+    }
+    pub fn owner(&self) -> Hash {
+        self.owner
+    }
+    // 只用证明或witness中的节点构建trie, 其余子树保留为HashNode, 访问到时返回MissingNode错误
+    pub fn from_witness(root: Hash, nodes: &[Vec<u8>]) -> Result<Self, TrieError> {
+        let db = MemoryDB::new();
+        for blob in nodes {
+            db.insert(keccak256(blob), blob.clone());
+        }
+        Trie::open(ID::trie_id(root), Rc::new(db))
     }
     // pub fn try_get_full_node(&self) -> Result<&FullNode, NodeError> {
     //     match &self.root_full_node {
@@ -78,7 +125,7 @@ impl Trie {
     //         None => Err(NodeError(String::from("not found value node")))
     //     }
     // }
-    pub fn try_update(&mut self, key: Vec<u8>, value: Option<Vec<u8>>) -> Result<(), Box<dyn Error>> {
+    pub fn try_update(&mut self, key: Vec<u8>, value: Option<Vec<u8>>) -> Result<(), TrieError> {
         self.unhashed += 1;
         let key = key_to_hex(key.as_slice());
         match value {
@@ -93,6 +140,140 @@ impl Trie {
         }
         Ok(())
     }
+    // 创建检查点, 返回的id用于回滚或丢弃, 可嵌套
+    pub fn checkpoint(&mut self) -> usize {
+        self.journal.push((Rc::clone(&self.root), self.unhashed));
+        self.journal.len() - 1
+    }
+    // 回滚到检查点id, id之后创建的检查点一并失效
+    pub fn revert_to(&mut self, id: usize) -> Result<(), TrieError> {
+        if id >= self.journal.len() {
+            return Err(TrieError::UnknownCheckpoint(id));
+        }
+        self.journal.truncate(id + 1);
+        let (root, unhashed) = self.journal.pop().unwrap();
+        self.root = root;
+        self.unhashed = unhashed;
+        Ok(())
+    }
+    // 丢弃检查点id及之后的检查点, 保留当前修改
+    pub fn discard_checkpoint(&mut self, id: usize) -> Result<(), TrieError> {
+        if id >= self.journal.len() {
+            return Err(TrieError::UnknownCheckpoint(id));
+        }
+        self.journal.truncate(id);
+        Ok(())
+    }
+    pub fn checkpoints(&self) -> usize {
+        self.journal.len()
+    }
+    // 复制trie, 节点共享, 两者的修改互不影响
+    pub fn copy(&self) -> Trie {
+        Trie { root: Rc::clone(&self.root), owner: self.owner, unhashed: self.unhashed, journal: self.journal.clone(), resolver: self.resolver.fork() }
+    }
+    // 当前状态的只读快照
+    pub fn snapshot(&self) -> TrieSnapshot {
+        TrieSnapshot::new(Rc::clone(&self.root), self.owner, self.resolver.fork())
+    }
+    pub fn iter(&self) -> TrieIterator {
+        TrieIterator::new(Rc::clone(&self.root), self.resolver.clone())
+    }
+    // 开始记录witness, 之后get/insert/delete访问到的存储节点都会被记录, 包括删除时合并节点需要的兄弟节点
+    pub fn start_witness(&mut self) {
+        self.resolver.start_witness();
+    }
+    // 当前记录的witness, 未开启记录时返回None
+    pub fn witness(&self) -> Option<Witness> {
+        self.resolver.witness()
+    }
+    // 停止记录并返回witness
+    pub fn stop_witness(&mut self) -> Option<Witness> {
+        self.resolver.stop_witness()
+    }
+    // 从start开始按key顺序遍历, 包括start本身
+    pub fn iter_from(&self, start: &[u8]) -> TrieIterator {
+        TrieIterator::with_start(Rc::clone(&self.root), self.resolver.clone(), range::hex_bound(start))
+    }
+    pub fn cache_stats(&self) -> CacheStats {
+        self.resolver.cache().borrow().stats()
+    }
+    // 从root开始查找key, 只需共享引用, 加载的节点缓存在resolver中
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, TrieError> {
+        let ret = get_node(Rc::clone(&self.root), key_to_hex(key).as_slice(), 0, &self.resolver)?;
+        Ok(ret.value)
+    }
+    // 批量查找, key排序后共享前缀的部分只遍历一次, 同一层未加载的节点合并读取
+    pub fn get_many(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>, TrieError> {
+        let hex_keys: Vec<Vec<u8>> = keys.iter().map(|k| key_to_hex(k)).collect();
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_by(|a, b| hex_keys[*a].cmp(&hex_keys[*b]));
+
+        let mut values = vec![None; keys.len()];
+        if keys.is_empty() {
+            return Ok(values);
+        }
+        // (节点, 已匹配的key长度, 经过该节点的key下标)
+        let mut stack: Vec<(Rc<dyn Node>, usize, Vec<usize>)> = vec![(Rc::clone(&self.root), 0, order)];
+        let mut pending: Vec<(Rc<dyn Node>, usize, Vec<usize>)> = Vec::new();
+        while !stack.is_empty() || !pending.is_empty() {
+            if stack.is_empty() {
+                // 本轮遇到的HashNode一次性解析
+                let mut reqs = Vec::with_capacity(pending.len());
+                for (n, pos, idxs) in pending.iter() {
+                    let hn = n.into_hash_node()?;
+                    reqs.push((hex_keys[idxs[0]][..*pos].to_vec(), Hash::from(hn.0)));
+                }
+                let resolved = self.resolver.resolve_many(&reqs)?;
+                for (rn, (_, pos, idxs)) in resolved.into_iter().zip(pending.drain(..)) {
+                    stack.push((rn, pos, idxs));
+                }
+                continue;
+            }
+            let (n, pos, idxs) = match stack.pop() {
+                Some(v) => v,
+                None => break,
+            };
+            self.resolver.touch(&n)?;
+            match n.kind() {
+                NodeType::NullNode => {},
+                NodeType::ValueNode => {
+                    let vn = n.into_value_node()?;
+                    for i in idxs {
+                        values[i] = Some(vn.0.clone());
+                    }
+                },
+                NodeType::ShortNode => {
+                    let sn = n.into_short_node()?;
+                    let matched: Vec<usize> = idxs.into_iter().filter(|i| hex_keys[*i][pos..].starts_with(&sn.key)).collect();
+                    if !matched.is_empty() {
+                        stack.push((sn.val, pos + sn.key.len(), matched));
+                    }
+                },
+                NodeType::FullNode => {
+                    let f_n = n.into_full_node()?;
+                    // key已排序, 相同nibble的key是连续的
+                    let mut start = 0;
+                    while start < idxs.len() {
+                        let key = &hex_keys[idxs[start]];
+                        if pos >= key.len() {
+                            return Err(TrieError::invalid("full node at end of key"));
+                        }
+                        let nibble = key[pos];
+                        let mut end = start + 1;
+                        while end < idxs.len() && hex_keys[idxs[end]][pos] == nibble {
+                            end += 1;
+                        }
+                        if let Some(child) = &f_n.children[nibble as usize] {
+                            stack.push((Rc::clone(child), pos + 1, idxs[start..end].to_vec()));
+                        }
+                        start = end;
+                    }
+                },
+                NodeType::HashNode => pending.push((n, pos, idxs)),
+            }
+        }
+        Ok(values)
+    }
     fn new_flag(&self) -> node::NodeFlag {
         node::NodeFlag{
             hash: None,
@@ -100,25 +281,26 @@ impl Trie {
         }
     }
     // 插入node
-    fn insert(&self, n: Rc<dyn Node>, prefix: Vec<u8>, key: Vec<u8>, value: Rc<dyn Node>) -> Result<(bool, Rc<dyn Node>), NodeError> {
-        if key.len() == 0 {
+    fn insert(&self, n: Rc<dyn Node>, prefix: Vec<u8>, key: Vec<u8>, value: Rc<dyn Node>) -> Result<(bool, Rc<dyn Node>), TrieError> {
+        self.resolver.touch(&n)?;
+        if key.is_empty() {
             // 如果key为空
             match n.kind() {
                 NodeType::ValueNode => {
                     let vn = n.into_value_node()?;
                     let val_node = value.into_value_node()?;
                     // *self.root_value_node = &Some(val_node);
-                    return Ok((!vn.equal(val_node), Rc::clone(&value)));
+                    Ok((!vn.equal(val_node), Rc::clone(&value)))
                 },
                 _ => {
-                    return Ok((true, Rc::clone(&value)));
+                    Ok((true, Rc::clone(&value)))
                 },
             }
         } else {
             // println!("kind {:?}", n.kind());
             match n.kind() {
                 NodeType::NullNode => {
-                    return Ok((true, Rc::new(ShortNode::new(key, Rc::clone(&value), self.new_flag()))));
+                    Ok((true, Rc::new(ShortNode::new(key, Rc::clone(&value), self.new_flag()))))
                 }
                 NodeType::ShortNode => {
                     let n = n.into_short_node()?;
@@ -152,13 +334,20 @@ impl Trie {
                         return Ok((true, Rc::new(branch)));
                     }
 
-                    return Ok((true, Rc::new(ShortNode::new(Vec::from(&key[..match_len]), Rc::new(branch), self.new_flag()))));
+                    Ok((true, Rc::new(ShortNode::new(Vec::from(&key[..match_len]), Rc::new(branch), self.new_flag()))))
                 }
                 NodeType::ValueNode => {
-                    return Err(NodeError::from("invalid node"))
+                    Err(TrieError::invalid("value node with remaining key"))
                 },
                 NodeType::HashNode => {
-                    return Err(NodeError::from("insert HashNode todo"))
+                    // 节点未加载, 先解析再插入
+                    let hn = n.into_hash_node()?;
+                    let rn = self.resolver.resolve(Hash::from(hn.0), &prefix)?;
+                    let (dirty, nn) = self.insert(rn, prefix, key, value)?;
+                    if !dirty {
+                        return Ok((false, n));
+                    }
+                    Ok((true, nn))
                 },
                 NodeType::FullNode => {
                     // let n = self.try_get_full_node()?;
@@ -183,13 +372,14 @@ impl Trie {
                     let mut f_n = n.into_full_node()?;
                     f_n.flags = self.new_flag();
                     f_n.children[key[0] as usize] = Some(nn);
-                    return Ok((true, Rc::new(f_n)));
+                    Ok((true, Rc::new(f_n)))
                 },
             }
         }
     }
 
-    fn delete(&self, n: Rc<dyn Node>, mut prefix: Vec<u8>, key: Vec<u8>) -> Result<(bool, Rc<dyn Node>), NodeError> {
+    fn delete(&self, n: Rc<dyn Node>, mut prefix: Vec<u8>, key: Vec<u8>) -> Result<(bool, Rc<dyn Node>), TrieError> {
+        self.resolver.touch(&n)?;
         // print!(" {:?} ", n.kind());
         match n.kind() {
             NodeType::ShortNode => {
@@ -211,14 +401,22 @@ impl Trie {
                         let child = child_node.into_short_node()?;
                         let mut new_key = sn.key.clone();
                         new_key.extend(child.key);
-                        return Ok((true, Rc::new(ShortNode::new(new_key, child.val, self.new_flag()))));
+                        Ok((true, Rc::new(ShortNode::new(new_key, child.val, self.new_flag()))))
                     },
                     _ => { // 如果是其它类型，直接作为shortNode的value
-                        return Ok((true, Rc::new(ShortNode::new(sn.key, child_node, self.new_flag()))));
+                        Ok((true, Rc::new(ShortNode::new(sn.key, child_node, self.new_flag()))))
                     }
                 }
             },
-            NodeType::HashNode => todo!(),
+            NodeType::HashNode => {
+                let hn = n.into_hash_node()?;
+                let rn = self.resolver.resolve(Hash::from(hn.0), &prefix)?;
+                let (dirty, nn) = self.delete(rn, prefix, key)?;
+                if !dirty {
+                    return Ok((false, n));
+                }
+                Ok((true, nn))
+            },
             NodeType::ValueNode => {
                 Ok((true, Rc::new(NilNode)))
             },
@@ -226,14 +424,16 @@ impl Trie {
                 Ok((false, Rc::new(NilNode)))
             },
             NodeType::FullNode => {
-
+                if key.is_empty() {
+                    return Err(TrieError::invalid("full node at end of key"));
+                }
                 let mut f_n = n.into_full_node()?;
                 let child_node = match &f_n.children[key[0] as usize] {
                     Some(v) => Rc::clone(v),
                     None => Rc::new(NilNode),
                 };
                 prefix.push(key[0]);
-                let (dirty, nn) = self.delete(child_node, prefix, Vec::from(&key[1..]))?;
+                let (dirty, nn) = self.delete(child_node, prefix.clone(), Vec::from(&key[1..]))?;
 
                 if !dirty {
                     return Ok((false, n));
@@ -252,7 +452,7 @@ impl Trie {
                 // 判断fullNode的子节点数量，如果只有一个，合并返回一个shoryNode
                 let mut pos = 100;
                 for (i,v) in f_n.children.iter().enumerate() {
-                    if let Some(_) = v {
+                    if v.is_some() {
                         if pos == 100 {
                             pos = i // 表示有一个子节点
                         } else {
@@ -266,6 +466,17 @@ impl Trie {
                 if pos < 17 { // 含有一个子节点
                     if pos != 16 { // pos不指向最后一个子节点
                         if let Some(nn) = &f_n.children[pos] {
+                            // 剩下的子节点未加载时需要先解析, 才能判断是否为shortNode
+                            let nn = if nn.kind() == NodeType::HashNode {
+                                let hn = nn.into_hash_node()?;
+                                prefix.pop();
+                                prefix.push(pos as u8);
+                                self.resolver.resolve(Hash::from(hn.0), &prefix)?
+                            } else {
+                                Rc::clone(nn)
+                            };
+                            // 已在内存中的兄弟节点也可能来自存储, 合并时需要它的数据
+                            self.resolver.touch(&nn)?;
                             if nn.kind() == NodeType::ShortNode  { // 最后一个子节点是shortNode,pos拼接key后返回一个shortNode
                                 let sn = nn.into_short_node()?;
                                 let mut new_key = Vec::from([pos as u8]);
@@ -286,54 +497,13 @@ impl Trie {
         }
     }
 
-    pub fn try_get(&mut self, n: Rc<dyn Node>, key: &[u8]) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
-        let ret = self.get(n, key_to_hex(key).as_slice(), 0)?;
+    pub fn try_get(&mut self, n: Rc<dyn Node>, key: &[u8]) -> Result<Option<Vec<u8>>, TrieError> {
+        let ret = get_node(n, key_to_hex(key).as_slice(), 0, &self.resolver)?;
         if ret.did_resolve {
             self.root = ret.new_node;
         }
         Ok(ret.value)
     }
-    fn get(&self, n: Rc<dyn Node>, key: &[u8], pos: usize) -> Result<GetResult, Box<dyn Error>> {
-        match n.kind() {
-            NodeType::NullNode => {
-                // println!("null node");
-                Ok(GetResult::from(None, false, Rc::new(NilNode)))
-            },
-            NodeType::ValueNode => {
-                let vn = n.into_value_node()?;
-                Ok(GetResult::from(Some(vn.0), false, n))
-            },
-            NodeType::ShortNode => {
-                let mut sn = n.into_short_node()?;
-                if sn.key.len() > key.len()- pos ||  !sn.key.eq(&Vec::from(&key[pos..(pos + sn.key.len())])){
-                    // println!("not found");
-                    return Ok(GetResult::from(None, false, n));
-                }
-                let ret = self.get(Rc::clone(&sn.val), key, pos + sn.key.len())?;
-                if ret.did_resolve {
-                    sn = n.into_short_node()?;
-                    sn.val = ret.new_node;
-                }
-                return Ok(GetResult::from(ret.value, ret.did_resolve, Rc::new(sn)));
-            },
-            NodeType::FullNode => {
-                let mut f_n = n.into_full_node()?;
-                let child_node = match &f_n.children[key[pos] as usize] {
-                    Some(v) => Rc::clone(v),
-                    None => Rc::new(NilNode),
-                };
-
-                let ret = self.get(child_node, key, pos+1)?;
-                if ret.did_resolve {
-                    f_n = n.into_full_node()?;
-                    f_n.children[key[pos] as usize] = Some(ret.new_node);
-                }
-                return Ok(GetResult::from(ret.value, ret.did_resolve, Rc::new(f_n)));
-            },
-            NodeType::HashNode => todo!(),
-        }
-    }
-
     // fn get2(&self, n: Rc<dyn Node>, key: Vec<u8>, pos: usize) -> Result<GetResult, Box<dyn Error>> {
     //     match n.kind() {
     //         NodeType::NullNode => {
@@ -376,27 +546,194 @@ impl Trie {
     // }
     
     // 计算默克尔hash根
-    pub fn hash(&mut self) -> Hash {
-        let (hs, cached) = self.hash_root();
+    pub fn hash(&mut self) -> Result<Hash, TrieError> {
+        let (hs, cached) = self.hash_root()?;
         self.root = cached; // 计算了hash后的root重新赋值
-        hs
+        Ok(hs)
+    }
+    // 提交所有修改, 返回root hash和需要写入存储的节点, 提交后节点均为干净节点
+    pub fn commit(&mut self) -> Result<(Hash, NodeSet), TrieError> {
+        let root = self.hash()?;
+        let mut set = NodeSet::new(self.owner);
+        self.journal.clear();
+        if self.root.kind() != NodeType::NullNode {
+            let mut c = Committer::new(&mut set);
+            self.root = c.commit(Rc::clone(&self.root), Vec::new())?;
+        }
+        // 原来存储过节点、现在已没有节点的路径记为删除, 按路径存储时需要
+        let mut stored = self.resolver.loaded_paths();
+        for path in stored.clone() {
+            if !set.contains(&path) && !stored_at(&self.root, &path, 0)? {
+                set.add_node(path.clone(), TrieNode::deleted());
+                stored.remove(&path);
+            }
+        }
+        stored.extend(set.iter().filter(|(_, n)| !n.is_deleted()).map(|(path, _)| path.clone()));
+        self.resolver.set_loaded_paths(stored);
+        Ok((root, set))
     }
-    fn hash_root(&mut self) -> (Hash, Rc<dyn Node>) {
+    // 把深度不小于depth的干净子树替换成HashNode, 之后访问时再从存储加载, 返回估算释放的字节数
+    // 仍被快照或检查点引用的节点不会真正释放
+    pub fn unload(&mut self, depth: usize) -> Result<usize, TrieError> {
+        if !self.resolver.has_reader() {
+            return Err(TrieError::BackendError("no node reader to reload unloaded nodes".to_string()));
+        }
+        let (n, freed) = self.unload_node(Rc::clone(&self.root), depth)?;
+        self.root = n;
+        Ok(freed)
+    }
+    fn unload_node(&self, n: Rc<dyn Node>, depth: usize) -> Result<(Rc<dyn Node>, usize), TrieError> {
+        if depth == 0 {
+            if let (Some(hn), false) = n.cache() {
+                if n.kind() == NodeType::ShortNode || n.kind() == NodeType::FullNode {
+                    let freed = subtree_size(&n).saturating_sub(std::mem::size_of::<HashNode>());
+                    return Ok((Rc::new(hn), freed));
+                }
+            }
+        }
+        let depth = depth.saturating_sub(1);
+        match n.kind() {
+            NodeType::ShortNode => {
+                let mut sn = n.into_short_node()?;
+                let (val, freed) = self.unload_node(Rc::clone(&sn.val), depth)?;
+                if freed == 0 {
+                    return Ok((n, 0));
+                }
+                sn.val = val;
+                Ok((Rc::new(sn), freed))
+            },
+            NodeType::FullNode => {
+                let mut f_n = n.into_full_node()?;
+                let mut total = 0;
+                for i in 0..16 {
+                    if let Some(child) = &f_n.children[i] {
+                        let (child, freed) = self.unload_node(Rc::clone(child), depth)?;
+                        f_n.children[i] = Some(child);
+                        total += freed;
+                    }
+                }
+                if total == 0 {
+                    return Ok((n, 0));
+                }
+                Ok((Rc::new(f_n), total))
+            },
+            _ => Ok((n, 0)),
+        }
+    }
+    fn hash_root(&mut self) -> Result<(Hash, Rc<dyn Node>), TrieError> {
         if self.root.kind() == NodeType::NullNode {
-            return (Hash::empty_root_hash(), Rc::clone(&self.root));
+            return Ok((Hash::empty_root_hash(), Rc::clone(&self.root)));
         }
-        println!("unhashed {}", self.unhashed);
         let mut h = Hasher::new(self.unhashed >= 100);
         let (hashed, cached) = h.hash_node(Rc::clone(&self.root), true);
         self.unhashed = 0; // 未hash的数量重置
         // 强转hashNode
-        let hn = hashed.into_hash_node().unwrap();
-        (Hash::from(hn.0), cached)
+        let hn = hashed.into_hash_node()?;
+        Ok((Hash::from(hn.0), cached))
     }
 }
 
 
 
+// 提交后path上是否有存储的节点, 未加载的子树视为未变动
+fn stored_at(n: &Rc<dyn Node>, path: &[u8], pos: usize) -> Result<bool, TrieError> {
+    match n.kind() {
+        NodeType::HashNode => Ok(true),
+        NodeType::ShortNode => {
+            if pos == path.len() {
+                return Ok(n.cache().0.is_some());
+            }
+            let sn = n.into_short_node()?;
+            if !path[pos..].starts_with(&sn.key) {
+                return Ok(false);
+            }
+            stored_at(&sn.val, path, pos + sn.key.len())
+        },
+        NodeType::FullNode => {
+            if pos == path.len() {
+                return Ok(n.cache().0.is_some());
+            }
+            let f_n = n.into_full_node()?;
+            match &f_n.children[path[pos] as usize] {
+                Some(child) => stored_at(child, path, pos + 1),
+                None => Ok(false),
+            }
+        },
+        _ => Ok(false),
+    }
+}
+
+// 估算内存中子树占用的字节数
+fn subtree_size(n: &Rc<dyn Node>) -> usize {
+    match n.kind() {
+        NodeType::ShortNode => match n.into_short_node() {
+            Ok(sn) => std::mem::size_of::<ShortNode>() + sn.key.len() + subtree_size(&sn.val),
+            Err(_) => 0,
+        },
+        NodeType::FullNode => match n.into_full_node() {
+            Ok(f_n) => std::mem::size_of::<FullNode>() + f_n.children.iter().flatten().map(subtree_size).sum::<usize>(),
+            Err(_) => 0,
+        },
+        NodeType::ValueNode => match n.into_value_node() {
+            Ok(vn) => std::mem::size_of::<ValueNode>() + vn.0.len(),
+            Err(_) => 0,
+        },
+        NodeType::HashNode => std::mem::size_of::<HashNode>(),
+        NodeType::NullNode => 0,
+    }
+}
+
+// 从节点n开始按hex key查找, 只读不修改trie
+fn get_node(n: Rc<dyn Node>, key: &[u8], pos: usize, resolver: &Resolver) -> Result<GetResult, TrieError> {
+    resolver.touch(&n)?;
+    match n.kind() {
+        NodeType::NullNode => {
+            // println!("null node");
+            Ok(GetResult::from(None, false, Rc::new(NilNode)))
+        },
+        NodeType::ValueNode => {
+            let vn = n.into_value_node()?;
+            Ok(GetResult::from(Some(vn.0), false, n))
+        },
+        NodeType::ShortNode => {
+            let mut sn = n.into_short_node()?;
+            if sn.key.len() > key.len()- pos ||  !sn.key.eq(&Vec::from(&key[pos..(pos + sn.key.len())])){
+                // println!("not found");
+                return Ok(GetResult::from(None, false, n));
+            }
+            let ret = get_node(Rc::clone(&sn.val), key, pos + sn.key.len(), resolver)?;
+            if ret.did_resolve {
+                sn = n.into_short_node()?;
+                sn.val = ret.new_node;
+            }
+            Ok(GetResult::from(ret.value, ret.did_resolve, Rc::new(sn)))
+        },
+        NodeType::FullNode => {
+            if pos >= key.len() {
+                return Err(TrieError::invalid("full node at end of key"));
+            }
+            let mut f_n = n.into_full_node()?;
+            let child_node = match &f_n.children[key[pos] as usize] {
+                Some(v) => Rc::clone(v),
+                None => Rc::new(NilNode),
+            };
+
+            let ret = get_node(child_node, key, pos+1, resolver)?;
+            if ret.did_resolve {
+                f_n = n.into_full_node()?;
+                f_n.children[key[pos] as usize] = Some(ret.new_node);
+            }
+            Ok(GetResult::from(ret.value, ret.did_resolve, Rc::new(f_n)))
+        },
+        NodeType::HashNode => {
+            let hn = n.into_hash_node()?;
+            let rn = resolver.resolve(Hash::from(hn.0), &key[..pos])?;
+            let ret = get_node(rn, key, pos, resolver)?;
+            Ok(GetResult::from(ret.value, true, ret.new_node))
+        },
+    }
+}
+
 pub struct GetResult {
     value: Option<Vec<u8>>,
     did_resolve: bool,
@@ -410,5 +747,243 @@ impl GetResult {
 
 pub mod node;
 pub mod common;
+pub mod asyncreader;
+pub mod cache;
+pub mod database;
+pub mod flatdb;
+pub mod hashdb;
+pub mod heal;
+pub mod integrity;
+pub mod kvstore;
+pub mod pathdb;
+pub mod prefetch;
+pub mod proof;
+pub mod pruner;
+pub mod range;
+pub mod nodeset;
+mod committer;
+mod json;
+mod resolver;
+mod rlp;
+pub mod error;
+pub mod iterator;
+pub mod snapshot;
+pub mod stacktrie;
+pub mod sync;
+pub mod state;
+pub mod witness;
 pub mod hasher;
 pub mod writer;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn root_of(kvs: &[(&str, &str)]) -> String {
+        let mut t = Trie::new(ID::trie_id(Hash::default()));
+        for (k, v) in kvs {
+            t.try_update(k.as_bytes().to_vec(), Some(v.as_bytes().to_vec())).unwrap();
+        }
+        hex::encode(*t.hash().unwrap())
+    }
+
+    // go-ethereum trie_test.go中的测试数据
+    #[test]
+    fn ethereum_root_vectors() {
+        assert_eq!(root_of(&[]), "56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421");
+        assert_eq!(common::keccak256(&[0x80]), Hash::empty_root_hash());
+        assert_eq!(root_of(&[("doe", "reindeer"), ("dog", "puppy"), ("dogglesworth", "cat")]), "8aad789dff2f538bca5d8ea56e8abe10f4c7ba3a5dea95fea4cd6e7c3a1168d3");
+        assert_eq!(root_of(&[("A", "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa")]), "d23786fb4a010da3ce639d66d5e904a11dbc02746d1ce25029e53290cabf28ab");
+    }
+
+    // 只有keccak256(rlp(""))是空root, 全0的hash按普通节点hash到存储中查找
+    #[test]
+    fn empty_root() {
+        assert!(Hash::empty_root_hash().is_empty_root());
+        assert!(!Hash::default().is_empty_root());
+        assert!(!common::keccak256(b"x").is_empty_root());
+        let db = Rc::new(MemoryDB::new());
+        let t = Trie::open(ID::trie_id(Hash::empty_root_hash()), db.clone()).unwrap();
+        assert_eq!(t.get(b"doe").unwrap(), None);
+        assert!(matches!(Trie::open(ID::trie_id(Hash::default()), db), Err(TrieError::MissingNode { .. })));
+    }
+
+    fn key(i: u32) -> Vec<u8> {
+        common::keccak256(&i.to_be_bytes()).to_vec()
+    }
+
+    fn value(i: u32) -> Vec<u8> {
+        vec![i as u8; 1 + (i % 40) as usize]
+    }
+
+    fn filled(n: u32) -> Trie {
+        let mut t = Trie::new(ID::trie_id(Hash::default()));
+        for i in 0..n {
+            t.try_update(key(i), Some(value(i))).unwrap();
+        }
+        t
+    }
+
+    // 嵌套的检查点按层回滚, 回滚后外层检查点仍可用
+    #[test]
+    fn nested_checkpoints() {
+        let mut t = filled(100);
+        let h0 = t.hash().unwrap();
+        let cp0 = t.checkpoint();
+        for i in 100..150 {
+            t.try_update(key(i), Some(value(i))).unwrap();
+        }
+        let h1 = t.hash().unwrap();
+        let cp1 = t.checkpoint();
+        for i in 0..50 {
+            t.try_update(key(i), None).unwrap();
+        }
+        t.try_update(key(120), Some(vec![0xff; 3])).unwrap();
+        assert_ne!(t.hash().unwrap(), h1);
+
+        t.revert_to(cp1).unwrap();
+        assert_eq!(t.checkpoints(), 1);
+        assert_eq!(t.hash().unwrap(), h1);
+        assert_eq!(t.get(&key(10)).unwrap(), Some(value(10)));
+        assert_eq!(t.revert_to(cp1), Err(TrieError::UnknownCheckpoint(cp1)));
+
+        t.revert_to(cp0).unwrap();
+        assert_eq!(t.checkpoints(), 0);
+        assert_eq!(t.hash().unwrap(), h0);
+        assert_eq!(t.get(&key(120)).unwrap(), None);
+    }
+
+    #[test]
+    fn discard_checkpoint() {
+        let mut t = filled(50);
+        let h0 = t.hash().unwrap();
+        let cp0 = t.checkpoint();
+        t.try_update(key(60), Some(value(60))).unwrap();
+        let cp1 = t.checkpoint();
+        t.try_update(key(61), Some(value(61))).unwrap();
+        let _ = t.checkpoint();
+        // 丢弃cp1及之后的检查点, 修改保留并归入cp0
+        t.discard_checkpoint(cp1).unwrap();
+        assert_eq!(t.checkpoints(), 1);
+        assert_eq!(t.get(&key(61)).unwrap(), Some(value(61)));
+        assert_eq!(t.discard_checkpoint(cp1), Err(TrieError::UnknownCheckpoint(cp1)));
+        t.revert_to(cp0).unwrap();
+        assert_eq!(t.hash().unwrap(), h0);
+    }
+
+    // 提交后检查点失效
+    #[test]
+    fn commit_clears_checkpoints() {
+        let mut t = filled(20);
+        let cp = t.checkpoint();
+        t.try_update(key(30), Some(value(30))).unwrap();
+        let (root, _) = t.commit().unwrap();
+        assert_eq!(t.checkpoints(), 0);
+        assert_eq!(t.revert_to(cp), Err(TrieError::UnknownCheckpoint(cp)));
+        assert_eq!(t.hash().unwrap(), root);
+    }
+
+    // 从存储打开后只用共享引用读取, 读取不改变root
+    #[test]
+    fn get_from_storage() {
+        let mut t = filled(300);
+        let (root, set) = t.commit().unwrap();
+        let db = Rc::new(MemoryDB::new());
+        db.update(&set);
+        let t = Trie::open(ID::trie_id(root), db).unwrap();
+        for i in 0..300 {
+            assert_eq!(t.get(&key(i)).unwrap(), Some(value(i)));
+        }
+        assert_eq!(t.get(&key(300)).unwrap(), None);
+        assert_eq!(t.get(b"").unwrap(), None);
+        assert_eq!(t.root.kind(), NodeType::FullNode);
+        assert_eq!(t.root.into_full_node().unwrap().children.iter().flatten().filter(|c| c.kind() == NodeType::HashNode).count(), 16);
+    }
+
+    // 记录批量读取的次数
+    struct CountingReader {
+        db: MemoryDB,
+        batches: std::cell::Cell<usize>,
+        reads: std::cell::Cell<usize>,
+    }
+
+    impl NodeReader for CountingReader {
+        fn node(&self, owner: Hash, path: &[u8], hash: Hash) -> Result<Option<Vec<u8>>, TrieError> {
+            self.reads.set(self.reads.get() + 1);
+            self.db.node(owner, path, hash)
+        }
+        fn nodes(&self, owner: Hash, reqs: &[(Vec<u8>, Hash)]) -> Result<Vec<Option<Vec<u8>>>, TrieError> {
+            self.batches.set(self.batches.get() + 1);
+            reqs.iter().map(|(path, hash)| self.node(owner, path, *hash)).collect()
+        }
+    }
+
+    // 结果与逐个get一致, 同一层未加载的节点合并为一次读取, 共享的节点只读取一次
+    #[test]
+    fn get_many_batches() {
+        let mut t = filled(300);
+        let (root, set) = t.commit().unwrap();
+        let reader = Rc::new(CountingReader { db: MemoryDB::new(), batches: Default::default(), reads: Default::default() });
+        reader.db.update(&set);
+        let t = Trie::open(ID::trie_id(root), reader.clone()).unwrap();
+
+        let mut keys: Vec<Vec<u8>> = (0..300).rev().map(key).collect();
+        keys.push(key(5));
+        keys.push(key(1000));
+        let refs: Vec<&[u8]> = keys.iter().map(|k| k.as_slice()).collect();
+        let values = t.get_many(&refs).unwrap();
+        assert!(reader.batches.get() <= 4);
+        assert_eq!(reader.reads.get(), set.len());
+        for (k, v) in keys.iter().zip(values) {
+            assert_eq!(t.get(k).unwrap(), v);
+        }
+        assert_eq!(t.get_many(&[]).unwrap(), Vec::<Option<Vec<u8>>>::new());
+    }
+
+    fn hash_nodes(n: &Rc<dyn Node>) -> usize {
+        match n.kind() {
+            NodeType::HashNode => 1,
+            NodeType::ShortNode => hash_nodes(&n.into_short_node().unwrap().val),
+            NodeType::FullNode => n.into_full_node().unwrap().children.iter().flatten().map(hash_nodes).sum(),
+            _ => 0,
+        }
+    }
+
+    // 卸载后深层只剩HashNode, 再次访问时从存储重新加载, 未提交的修改保留
+    #[test]
+    fn unload_committed_subtrees() {
+        let mut t = filled(300);
+        let (root, set) = t.commit().unwrap();
+        let db = Rc::new(MemoryDB::new());
+        db.update(&set);
+        // 更新后提交, 路径上的节点留在内存中成为干净节点
+        let mut t = Trie::open(ID::trie_id(root), db.clone()).unwrap();
+        for i in 0..300 {
+            t.try_update(key(i), Some(vec![i as u8; 45])).unwrap();
+        }
+        let (root, set) = t.commit().unwrap();
+        db.update(&set);
+        t.try_update(key(7), Some(vec![7; 50])).unwrap();
+        let before = hash_nodes(&t.root);
+
+        let freed = t.unload(1).unwrap();
+        assert!(freed > 0);
+        assert!(hash_nodes(&t.root) > before);
+        // 只有16个子节点中被修改的那个保留在内存中
+        let children = t.root.into_full_node().unwrap().children;
+        assert_eq!(children.iter().flatten().filter(|c| c.kind() == NodeType::HashNode).count(), 15);
+        assert_eq!(t.unload(1).unwrap(), 0);
+
+        assert_eq!(t.get(&key(7)).unwrap(), Some(vec![7; 50]));
+        assert_eq!(t.get(&key(8)).unwrap(), Some(vec![8; 45]));
+        t.try_update(key(7), Some(vec![7; 45])).unwrap();
+        assert_eq!(t.hash().unwrap(), root);
+    }
+
+    #[test]
+    fn unload_needs_reader() {
+        let mut t = filled(10);
+        t.commit().unwrap();
+        assert!(matches!(t.unload(0), Err(TrieError::BackendError(_))));
+    }
+}
@@ -0,0 +1,102 @@
+use std::rc::Rc;
+
+use crate::{common::{compact_to_hex, has_term, Hash}, rlp, TrieError};
+
+use super::{FullNode, HashNode, Node, NodeFlag, NodeType, ShortNode, ValueNode};
+
+// 解码存储的节点数据, hash为节点数据的hash, 内嵌节点传None
+pub fn decode_node(hash: Option<HashNode>, buf: &[u8]) -> Result<Rc<dyn Node>, TrieError> {
+    let (elems, _) = rlp::split_list(buf)?;
+    match rlp::count_values(elems)? {
+        2 => decode_short(hash, elems),
+        17 => decode_full(hash, elems),
+        c => Err(TrieError::DecodeError(format!("invalid number of list elements: {}", c))),
+    }
+}
+
+fn decode_short(hash: Option<HashNode>, elems: &[u8]) -> Result<Rc<dyn Node>, TrieError> {
+    let (kbuf, rest) = rlp::split_string(elems)?;
+    let flags = NodeFlag { hash, dirty: false };
+    let key = compact_to_hex(kbuf);
+    if has_term(&key) {
+        // 叶子节点, value是字符串
+        let (val, _) = rlp::split_string(rest)?;
+        return Ok(Rc::new(ShortNode::new(key, Rc::new(ValueNode::new(val)), flags)));
+    }
+    let (r, _) = decode_ref(rest)?;
+    match r {
+        Some(val) => Ok(Rc::new(ShortNode::new(key, val, flags))),
+        None => Err(TrieError::DecodeError("extension node with empty child".to_string())),
+    }
+}
+
+fn decode_full(hash: Option<HashNode>, mut elems: &[u8]) -> Result<Rc<dyn Node>, TrieError> {
+    let mut n = FullNode::from(NodeFlag { hash, dirty: false });
+    for i in 0..16 {
+        let (r, rest) = decode_ref(elems)?;
+        n.children[i] = r;
+        elems = rest;
+    }
+    let (val, _) = rlp::split_string(elems)?;
+    if !val.is_empty() {
+        n.children[16] = Some(Rc::new(ValueNode::new(val)));
+    }
+    Ok(Rc::new(n))
+}
+
+// 子节点引用及剩余数据
+type DecodedRef<'a> = (Option<Rc<dyn Node>>, &'a [u8]);
+
+// 解码子节点引用: 空字符串、32字节hash或内嵌节点
+fn decode_ref(buf: &[u8]) -> Result<DecodedRef<'_>, TrieError> {
+    let (kind, val, rest) = rlp::split(buf)?;
+    match kind {
+        rlp::Kind::List => {
+            let size = buf.len() - rest.len();
+            if size >= 32 {
+                return Err(TrieError::DecodeError(format!("oversized embedded node (size is {} bytes, want size < 32)", size)));
+            }
+            let n = decode_node(None, &buf[..size])?;
+            Ok((Some(n), rest))
+        },
+        rlp::Kind::String if val.is_empty() => Ok((None, rest)),
+        rlp::Kind::String if val.len() == 32 => {
+            let mut hs = [0_u8; 32];
+            hs.copy_from_slice(val);
+            Ok((Some(Rc::new(HashNode::from(hs))), rest))
+        },
+        rlp::Kind::String => Err(TrieError::DecodeError(format!("invalid RLP string size {} (want 0 or 32)", val.len()))),
+    }
+}
+
+// 节点数据中引用的所有子节点hash, 包括内嵌节点中的引用, 路径为相对该节点的hex路径
+pub fn child_hashes(buf: &[u8]) -> Result<Vec<(Vec<u8>, Hash)>, TrieError> {
+    let mut ret = Vec::new();
+    let n = decode_node(None, buf)?;
+    gather_children(&n, Vec::new(), &mut ret)?;
+    Ok(ret)
+}
+
+fn gather_children(n: &Rc<dyn Node>, path: Vec<u8>, ret: &mut Vec<(Vec<u8>, Hash)>) -> Result<(), TrieError> {
+    match n.kind() {
+        NodeType::HashNode => ret.push((path, Hash::from(n.into_hash_node()?.0))),
+        NodeType::ShortNode => {
+            let sn = n.into_short_node()?;
+            let mut child_path = path;
+            child_path.extend(&sn.key);
+            gather_children(&sn.val, child_path, ret)?;
+        },
+        NodeType::FullNode => {
+            let f_n = n.into_full_node()?;
+            for (i, child) in f_n.children.iter().enumerate().take(16) {
+                if let Some(child) = child {
+                    let mut child_path = path.clone();
+                    child_path.push(i as u8);
+                    gather_children(child, child_path, ret)?;
+                }
+            }
+        },
+        _ => {},
+    }
+    Ok(())
+}
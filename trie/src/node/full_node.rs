@@ -26,11 +26,14 @@ pub struct FullNode {
 }
 
 impl FullNode {
-    pub fn default() -> Self {
-        FullNode { children: [None,None,None,None,None,None,None,None,None,None,None,None,None,None,None,None,None], flags: NodeFlag::default() }
-    }
     pub(crate) fn from(flags: NodeFlag) -> Self {
-        FullNode { children: [None,None,None,None,None,None,None,None,None,None,None,None,None,None,None,None,None], flags: flags }
+        FullNode { children: [None,None,None,None,None,None,None,None,None,None,None,None,None,None,None,None,None], flags }
+    }
+}
+
+impl Default for FullNode {
+    fn default() -> Self {
+        FullNode::from(NodeFlag::default())
     }
 }
 
@@ -50,6 +53,7 @@ impl Node for FullNode {
     }
 
     fn encode(&self, w: Rc<RefCell<EncodeBuffer>>) {
+        let offset = w.borrow().list();
         for v in self.children.iter() {
             match v {
                 None => {
@@ -61,6 +65,7 @@ impl Node for FullNode {
                 },
             }
         }
+        w.borrow_mut().list_end(offset);
     }
 
     fn fstring(&self, ind: String) -> String {
@@ -71,7 +76,7 @@ impl Node for FullNode {
                     resp = resp.add(format!("{}: <nil> ", INDICES[i]).as_str());
                 },
                 Some(node) => {
-                    let fmt_str = format!("{}: {}", INDICES[i].clone(), node.fstring(ind.clone()+"  "));
+                    let fmt_str = format!("{}: {}", INDICES[i], node.fstring(ind.clone()+"  "));
                     resp = resp.add(fmt_str.as_str());
                 }
             }
@@ -80,14 +85,11 @@ impl Node for FullNode {
         // resp.add(&g)
     }
 
-    fn into_full_node(&self) -> Result<FullNode, crate::NodeError> {
+    fn into_full_node(&self) -> Result<FullNode, crate::TrieError> {
         let mut cp = FullNode::default();
         for (i,v) in self.children.iter().enumerate() {
-            match v {
-                Some(n) => {
-                    cp.children[i] = Some(Rc::clone(n));
-                },
-                None => {}
+            if let Some(n) = v {
+                cp.children[i] = Some(Rc::clone(n));
             }
         }
         cp.flags = self.flags.clone();
@@ -4,6 +4,7 @@ use crate::writer::EncodeBuffer;
 use super::Node;
 
 
+#[derive(Default)]
 pub struct HashNode (pub(crate) [u8; 32]);
 // type HashNode2 = Box<[u8; 32]>;
 
@@ -29,11 +30,8 @@ impl HashNode {
     pub fn from(v: [u8;32]) -> Self {
         HashNode(v)
     }
-    pub fn default() -> Self {
-        HashNode([0_u8;32])
-    }
     pub fn copy(&self) -> Self {
-        HashNode(self.0.clone())
+        HashNode(self.0)
     }
 }
 
@@ -41,7 +39,7 @@ impl HashNode {
 impl Node for HashNode {
     // type MyType = HashNode;
     fn cache(&self) -> (Option<HashNode>, bool) {
-        return (None, true);
+        (None, true)
     }
 
     fn encode(&self, w: Rc<RefCell<EncodeBuffer>>) {
@@ -55,7 +53,7 @@ impl Node for HashNode {
         format!("<{}>", hex::encode(self.0))
     }
 
-    fn into_hash_node(&self) -> Result<HashNode, crate::NodeError> {
+    fn into_hash_node(&self) -> Result<HashNode, crate::TrieError> {
         Ok(HashNode(self.0))
     }
 }
@@ -17,17 +17,22 @@ pub trait Node {
     fn encode(&self, w: Rc<RefCell<EncodeBuffer>>);
     fn fstring(&self, v: String) -> String;
     fn kind(&self) -> NodeType;
-    fn into_value_node(&self) -> Result<ValueNode, NodeError> {
-        Err(NodeError::from("not found"))
+    // 节点通过Rc共享, into_*返回节点的浅拷贝而不是取出节点本身
+    #[allow(clippy::wrong_self_convention)]
+    fn into_value_node(&self) -> Result<ValueNode, TrieError> {
+        Err(TrieError::invalid("not a value node"))
     }
-    fn into_hash_node(&self) -> Result<HashNode, NodeError> {
-        Err(NodeError::from("not found"))
+    #[allow(clippy::wrong_self_convention)]
+    fn into_hash_node(&self) -> Result<HashNode, TrieError> {
+        Err(TrieError::invalid("not a hash node"))
     }
-    fn into_full_node(&self) -> Result<FullNode, NodeError> {
-        Err(NodeError::from("not found"))
+    #[allow(clippy::wrong_self_convention)]
+    fn into_full_node(&self) -> Result<FullNode, TrieError> {
+        Err(TrieError::invalid("not a full node"))
     }
-    fn into_short_node(&self) -> Result<ShortNode, NodeError> {
-        Err(NodeError::from("not found"))
+    #[allow(clippy::wrong_self_convention)]
+    fn into_short_node(&self) -> Result<ShortNode, TrieError> {
+        Err(TrieError::invalid("not a short node"))
     }
     fn to_string(&self) -> String {
         self.fstring(String::default())
@@ -36,7 +41,7 @@ pub trait Node {
 
 // impl<T> NodeClone for T where T: Node + Clone {}
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct NilNode;
 impl NilNode {
     pub fn new() -> Self {
@@ -50,10 +55,10 @@ impl Node for NilNode {
         (None, false)
     }
 
-    fn encode(&self, w: Rc<RefCell<EncodeBuffer>>) {
+    fn encode(&self, _: Rc<RefCell<EncodeBuffer>>) {
     }
 
-    fn fstring(&self, v: String) -> String {
+    fn fstring(&self, _: String) -> String {
         String::default()
     }
 
@@ -81,16 +86,12 @@ impl NodeFlag {
         NodeFlag { hash: None, dirty: false }
     }
     pub fn get_hash_node(&self) -> Option<HashNode> {
-        match &self.hash {
-            Some(v) => Some(v.copy()),
-            None => None,
-        }
+        self.hash.as_ref().map(|v| v.copy())
     }
 }
 
 pub mod full_node;
 use std::cell::RefCell;
-use std::io;
 use std::rc::Rc;
 
 pub use full_node::FullNode;
@@ -102,9 +103,12 @@ pub use hash_node::HashNode;
 pub mod short_node;
 pub use short_node::ShortNode;
 
+pub mod decode;
+pub use decode::{decode_node, child_hashes};
+
 pub mod value_node;
 pub use value_node::ValueNode;
 pub use value_node::NIL_VALUE_NODE;
 
-use crate::NodeError;
+use crate::TrieError;
 use crate::writer::EncodeBuffer;
\ No newline at end of file
@@ -1,5 +1,5 @@
 
-use std::{io::{Write, self}, rc::Rc, cell::RefCell};
+use std::{rc::Rc, cell::RefCell};
 
 use crate::writer::EncodeBuffer;
 
@@ -14,7 +14,7 @@ pub struct ShortNode {
 
 impl ShortNode {
     pub(crate) fn new(key: Vec<u8>, val: Rc<dyn Node>, flags: super::NodeFlag) -> Self {
-        ShortNode{key: key, val: val, flags: flags}
+        ShortNode{key, val, flags}
     }
 }
 
@@ -28,7 +28,7 @@ impl ShortNode {
 impl Node for ShortNode {
     // type MyType = ShortNode<T>;
     fn cache(&self) -> (Option<HashNode>, bool) {
-        return (self.flags.get_hash_node(), self.flags.dirty);
+        (self.flags.get_hash_node(), self.flags.dirty)
     }
 
     // fn encode(&self, w: Rc<RefCell<dyn Write>>) -> io::Result<usize> {
@@ -43,30 +43,33 @@ impl Node for ShortNode {
     //     Ok(size)
     // }
     fn encode(&self, w: Rc<RefCell<EncodeBuffer>>) {
-        {
+        let offset = {
             let mut wri = w.borrow_mut();
+            let offset = wri.list();
             wri.write_bytes(self.key.as_slice());
-        }
+            offset
+        };
 
         if self.val.kind() != super::NodeType::NullNode {
             // self.val.encode(Rc::clone(&w));
-            self.val.encode(w);
+            self.val.encode(Rc::clone(&w));
         } else {
             w.borrow_mut().write(0x80);
         }
+        w.borrow_mut().list_end(offset);
     }
 
     fn fstring(&self, ind: String) -> String {
         let k_str = self.key.as_slice();
         let v_str = self.val.as_ref().fstring(ind+"  ");
-        format!("{{{}: {}}} ", hex::encode(&k_str), v_str)
+        format!("{{{}: {}}} ", hex::encode(k_str), v_str)
     }
 
     fn kind(&self) -> super::NodeType {
         super::NodeType::ShortNode
     }
 
-    fn into_short_node(&self) -> Result<ShortNode, crate::NodeError> {
+    fn into_short_node(&self) -> Result<ShortNode, crate::TrieError> {
         Ok(ShortNode { key: self.key.clone(), val: Rc::clone(&self.val), flags: self.flags.clone() })
     }
 }
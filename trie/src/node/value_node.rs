@@ -6,6 +6,7 @@ use super::Node;
 
 pub const NIL_VALUE_NODE: ValueNode = ValueNode(Vec::new());
 
+#[derive(Default)]
 pub struct ValueNode (pub Vec<u8>);
 
 pub trait ToValueNode {
@@ -14,13 +15,13 @@ pub trait ToValueNode {
 
 impl ToValueNode for Vec<u8> {
     fn to_value_node(&self) -> ValueNode {
-        ValueNode((&self).to_vec())
+        ValueNode(self.to_vec())
     }
 }
 
 impl ToValueNode for &[u8] {
     fn to_value_node(&self) -> ValueNode {
-        ValueNode((&self).to_vec())
+        ValueNode(self.to_vec())
     }
 }
 
@@ -28,9 +29,6 @@ impl ValueNode {
     pub fn new<T: ToValueNode>(v: T) -> Self {
         v.to_value_node()
     }
-    pub fn default() -> Self {
-        ValueNode(Vec::new())
-    }
     pub fn copy(&self) -> ValueNode {
         ValueNode(self.0.clone())
     }
@@ -42,7 +40,7 @@ impl ValueNode {
 impl Node for ValueNode {
     // type MyType = ValueNode;
     fn cache(&self) -> (Option<HashNode>, bool) {
-        return (None, true);
+        (None, true)
     }
 
     fn encode(&self, w: Rc<RefCell<EncodeBuffer>>) {
@@ -58,7 +56,7 @@ impl Node for ValueNode {
     fn kind(&self) -> super::NodeType {
         super::NodeType::ValueNode
     }
-    fn into_value_node(&self) -> Result<ValueNode, crate::NodeError> {
+    fn into_value_node(&self) -> Result<ValueNode, crate::TrieError> {
         Ok(ValueNode(self.0.clone()))
     }
 }
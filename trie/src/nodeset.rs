@@ -0,0 +1,45 @@
+use std::collections::BTreeMap;
+
+use crate::common::Hash;
+
+// 提交后需要写入存储的节点, blob为空表示该路径上的节点已被删除
+pub struct TrieNode {
+    pub hash: Hash,
+    pub blob: Vec<u8>,
+}
+
+impl TrieNode {
+    pub fn deleted() -> Self {
+        TrieNode { hash: Hash::default(), blob: Vec::new() }
+    }
+    pub fn is_deleted(&self) -> bool {
+        self.blob.is_empty()
+    }
+}
+
+// 一次提交产生的节点集合, 按hex路径排序
+pub struct NodeSet {
+    pub owner: Hash,
+    pub(crate) nodes: BTreeMap<Vec<u8>, TrieNode>,
+}
+
+impl NodeSet {
+    pub fn new(owner: Hash) -> Self {
+        NodeSet { owner, nodes: BTreeMap::new() }
+    }
+    pub fn add_node(&mut self, path: Vec<u8>, node: TrieNode) {
+        self.nodes.insert(path, node);
+    }
+    pub fn contains(&self, path: &[u8]) -> bool {
+        self.nodes.contains_key(path)
+    }
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (&Vec<u8>, &TrieNode)> {
+        self.nodes.iter()
+    }
+}
@@ -0,0 +1,48 @@
+use crate::{common::{to_hash, Hash}, rlp, writer::EncodeBuffer, TrieError};
+
+// 一个区块的反向差异: 提交前每个被修改路径上的原始节点数据
+pub struct StateHistory {
+    // 提交前的root
+    pub parent: Hash,
+    // 提交后的root
+    pub root: Hash,
+    // (owner, hex路径, 原节点数据), 原来不存在节点时为空
+    pub prev: Vec<(Hash, Vec<u8>, Vec<u8>)>,
+}
+
+impl StateHistory {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = EncodeBuffer::new();
+        let offset = w.list();
+        w.write_bytes(self.parent.as_slice());
+        w.write_bytes(self.root.as_slice());
+        let entries = w.list();
+        for (owner, path, blob) in self.prev.iter() {
+            let entry = w.list();
+            w.write_bytes(owner.as_slice());
+            w.write_bytes(path);
+            w.write_bytes(blob);
+            w.list_end(entry);
+        }
+        w.list_end(entries);
+        w.list_end(offset);
+        w.encode_bytes()
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, TrieError> {
+        let (elems, _) = rlp::split_list(buf)?;
+        let (parent, rest) = rlp::split_string(elems)?;
+        let (root, rest) = rlp::split_string(rest)?;
+        let (mut entries, _) = rlp::split_list(rest)?;
+        let mut prev = Vec::new();
+        while !entries.is_empty() {
+            let (entry, rest) = rlp::split_list(entries)?;
+            let (owner, e) = rlp::split_string(entry)?;
+            let (path, e) = rlp::split_string(e)?;
+            let (blob, _) = rlp::split_string(e)?;
+            prev.push((to_hash(owner)?, path.to_vec(), blob.to_vec()));
+            entries = rest;
+        }
+        Ok(StateHistory { parent: to_hash(parent)?, root: to_hash(root)?, prev })
+    }
+}
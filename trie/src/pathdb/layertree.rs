@@ -0,0 +1,256 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::{common::Hash, database::NodeReader, nodeset::NodeSet, TrieError};
+
+use super::PathDB;
+
+// 默认最多保留128层内存差异层
+pub const DEFAULT_MAX_DIFF_LAYERS: usize = 128;
+
+// 一个root对应的内存差异层, 保存该次提交的所有节点
+pub struct DiffLayer {
+    pub root: Hash,
+    pub parent: Hash,
+    sets: Vec<NodeSet>,
+}
+
+impl DiffLayer {
+    // 层内该路径的节点, Some(空)表示被删除, None表示本层未修改
+    fn node(&self, owner: &Hash, path: &[u8]) -> Option<&[u8]> {
+        self.sets.iter()
+            .filter(|set| set.owner == *owner)
+            .find_map(|set| set.nodes.get(path))
+            .map(|n| n.blob.as_slice())
+    }
+}
+
+// 磁盘上的基础层加若干内存差异层, 可读取最近任意root的状态
+// 差异层超过上限时, 最底层合并写入磁盘
+pub struct LayerTree {
+    base: Rc<PathDB>,
+    layers: RefCell<HashMap<Hash, Rc<DiffLayer>>>,
+    max_diff_layers: usize,
+}
+
+impl LayerTree {
+    pub fn new(base: Rc<PathDB>, max_diff_layers: usize) -> Self {
+        LayerTree { base, layers: RefCell::new(HashMap::new()), max_diff_layers }
+    }
+
+    pub fn disk_root(&self) -> Result<Hash, TrieError> {
+        Ok(self.base.meta()?.root)
+    }
+
+    // 在parent之上增加一层, parent须为磁盘root或已有的差异层
+    pub fn add(&self, parent: Hash, root: Hash, sets: Vec<NodeSet>) -> Result<(), TrieError> {
+        if root == parent {
+            return Ok(());
+        }
+        let disk_root = self.disk_root()?;
+        if parent != disk_root && !self.layers.borrow().contains_key(&parent) {
+            return Err(TrieError::BackendError(format!("parent layer {} not found", parent)));
+        }
+        self.layers.borrow_mut().insert(root, Rc::new(DiffLayer { root, parent, sets }));
+        // 超出深度的底层依次合并进磁盘
+        loop {
+            let chain = self.chain(root)?;
+            if chain.len() <= self.max_diff_layers {
+                return Ok(());
+            }
+            match chain.last() {
+                Some(bottom) => self.flatten(bottom.root)?,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    // root对应状态的只读视图, 先查差异层, 再查磁盘
+    pub fn reader(&self, root: Hash) -> Result<LayerReader, TrieError> {
+        let layers = self.chain(root)?;
+        Ok(LayerReader { layers, base: Rc::clone(&self.base) })
+    }
+
+    pub fn len(&self) -> usize {
+        self.layers.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.borrow().is_empty()
+    }
+
+    // 从root到磁盘层的差异层, 最新的在前
+    fn chain(&self, root: Hash) -> Result<Vec<Rc<DiffLayer>>, TrieError> {
+        let disk_root = self.disk_root()?;
+        let layers = self.layers.borrow();
+        let mut chain = Vec::new();
+        let mut cur = root;
+        while cur != disk_root {
+            match layers.get(&cur) {
+                Some(l) => {
+                    cur = l.parent;
+                    chain.push(Rc::clone(l));
+                },
+                None => return Err(TrieError::BackendError(format!("state {} is not available", root))),
+            }
+        }
+        Ok(chain)
+    }
+
+    // 把紧挨磁盘层的root层写入磁盘, 不再连接到新磁盘root的分叉层一并丢弃
+    fn flatten(&self, root: Hash) -> Result<(), TrieError> {
+        let layer = match self.layers.borrow_mut().remove(&root) {
+            Some(l) => l,
+            None => return Ok(()),
+        };
+        self.base.commit(layer.parent, layer.root, &layer.sets)?;
+        let mut layers = self.layers.borrow_mut();
+        loop {
+            let stale: Vec<Hash> = layers.values()
+                .filter(|l| l.parent != root && !layers.contains_key(&l.parent))
+                .map(|l| l.root)
+                .collect();
+            if stale.is_empty() {
+                break;
+            }
+            for h in stale {
+                layers.remove(&h);
+            }
+        }
+        Ok(())
+    }
+}
+
+// 某个root的一致视图, 持有所经过的差异层
+pub struct LayerReader {
+    layers: Vec<Rc<DiffLayer>>,
+    base: Rc<PathDB>,
+}
+
+impl NodeReader for LayerReader {
+    fn node(&self, owner: Hash, path: &[u8], hash: Hash) -> Result<Option<Vec<u8>>, TrieError> {
+        for l in self.layers.iter() {
+            if let Some(blob) = l.node(&owner, path) {
+                if blob.is_empty() {
+                    return Ok(None);
+                }
+                return Ok(Some(blob.to_vec()));
+            }
+        }
+        // 磁盘层会校验hash, 已被其他分叉覆盖的节点视为不存在
+        self.base.node(owner, path, hash)
+    }
+    fn unchecked_node(&self, owner: Hash, path: &[u8], _: Hash) -> Result<Option<Vec<u8>>, TrieError> {
+        for l in self.layers.iter() {
+            if let Some(blob) = l.node(&owner, path) {
+                if blob.is_empty() {
+                    return Ok(None);
+                }
+                return Ok(Some(blob.to_vec()));
+            }
+        }
+        self.base.raw_node(owner, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{common::keccak256, kvstore::MemoryStore, Trie, ID};
+
+    fn key(i: u32) -> Vec<u8> {
+        keccak256(&i.to_be_bytes()).to_vec()
+    }
+
+    // 在parent状态上写入key(0..n)为v, 作为新的差异层
+    fn add_layer(tree: &LayerTree, parent: Hash, n: u32, v: u8) -> Hash {
+        let mut t = Trie::open(ID::trie_id(parent), Rc::new(tree.reader(parent).unwrap())).unwrap();
+        for i in 0..n {
+            t.try_update(key(i), Some(vec![v; 40])).unwrap();
+        }
+        let (root, set) = t.commit().unwrap();
+        tree.add(parent, root, vec![set]).unwrap();
+        root
+    }
+
+    fn read(tree: &LayerTree, root: Hash, i: u32) -> Option<Vec<u8>> {
+        let t = Trie::open(ID::trie_id(root), Rc::new(tree.reader(root).unwrap())).unwrap();
+        t.get(&key(i)).unwrap()
+    }
+
+    // 每个root都能读到自己的状态, 超出层数的底层合并进磁盘
+    #[test]
+    fn layers_and_flatten() {
+        let base = Rc::new(PathDB::new(Rc::new(MemoryStore::new())));
+        let tree = LayerTree::new(Rc::clone(&base), 3);
+        let mut roots = vec![Hash::empty_root_hash()];
+        for v in 1..=3u8 {
+            let parent = roots[roots.len() - 1];
+            roots.push(add_layer(&tree, parent, 100 * v as u32, v));
+        }
+        assert_eq!(tree.len(), 3);
+        assert_eq!(tree.disk_root().unwrap(), Hash::empty_root_hash());
+        for v in 1..=3u8 {
+            assert_eq!(read(&tree, roots[v as usize], 50), Some(vec![v; 40]));
+            assert_eq!(read(&tree, roots[v as usize], 100 * v as u32), None);
+        }
+
+        roots.push(add_layer(&tree, roots[3], 10, 4));
+        assert_eq!(tree.len(), 3);
+        assert_eq!(tree.disk_root().unwrap(), roots[1]);
+        assert_eq!(base.meta().unwrap().id, 1);
+        assert!(tree.reader(roots[0]).is_err());
+        assert_eq!(read(&tree, roots[4], 5), Some(vec![4; 40]));
+        assert_eq!(read(&tree, roots[4], 50), Some(vec![3; 40]));
+        assert_eq!(read(&tree, roots[2], 150), Some(vec![2; 40]));
+    }
+
+    // 同一个parent上的分叉都可读, 合并其中一支后另一支被丢弃
+    #[test]
+    fn forks() {
+        let base = Rc::new(PathDB::new(Rc::new(MemoryStore::new())));
+        let tree = LayerTree::new(Rc::clone(&base), 2);
+        let r1 = add_layer(&tree, Hash::empty_root_hash(), 100, 1);
+        let a = add_layer(&tree, r1, 10, 2);
+        let b = add_layer(&tree, r1, 20, 3);
+        assert_eq!(tree.len(), 3);
+        assert_eq!(read(&tree, a, 5), Some(vec![2; 40]));
+        assert_eq!(read(&tree, b, 5), Some(vec![3; 40]));
+        assert_eq!(read(&tree, b, 50), Some(vec![1; 40]));
+
+        let a2 = add_layer(&tree, a, 10, 4);
+        assert_eq!(tree.disk_root().unwrap(), r1);
+        assert_eq!(read(&tree, a2, 5), Some(vec![4; 40]));
+        // b仍然连接在磁盘root上
+        assert_eq!(read(&tree, b, 5), Some(vec![3; 40]));
+        let a3 = add_layer(&tree, a2, 10, 5);
+        assert_eq!(tree.disk_root().unwrap(), a);
+        assert!(tree.reader(b).is_err());
+        assert_eq!(tree.len(), 2);
+        assert_eq!(read(&tree, a3, 5), Some(vec![5; 40]));
+        assert!(matches!(tree.add(b, keccak256(b"x"), Vec::new()), Err(TrieError::BackendError(_))));
+    }
+
+    // 差异层中删除的节点不会从磁盘层读到旧数据
+    #[test]
+    fn deleted_nodes_hide_disk() {
+        let base = Rc::new(PathDB::new(Rc::new(MemoryStore::new())));
+        let tree = LayerTree::new(Rc::clone(&base), 1);
+        let r1 = add_layer(&tree, Hash::empty_root_hash(), 200, 1);
+        let r2 = add_layer(&tree, r1, 1, 2);
+        assert_eq!(tree.disk_root().unwrap(), r1);
+        let mut t = Trie::open(ID::trie_id(r2), Rc::new(tree.reader(r2).unwrap())).unwrap();
+        for i in 0..199 {
+            t.try_update(key(i), None).unwrap();
+        }
+        let (r3, set) = t.commit().unwrap();
+        let deleted: Vec<Vec<u8>> = set.iter().filter(|(_, n)| n.is_deleted()).map(|(p, _)| p.clone()).collect();
+        assert!(!deleted.is_empty());
+        tree.add(r2, r3, vec![set]).unwrap();
+        let reader = tree.reader(r3).unwrap();
+        for path in deleted {
+            assert_eq!(reader.unchecked_node(Hash::default(), &path, Hash::default()).unwrap(), None);
+            assert!(base.raw_node(Hash::default(), &path).unwrap().is_some());
+        }
+        assert_eq!(read(&tree, r3, 199), Some(vec![1; 40]));
+    }
+}
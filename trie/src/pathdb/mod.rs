@@ -0,0 +1,354 @@
+use std::rc::Rc;
+
+use crate::{common::{keccak256, to_hash, Hash}, database::{NodeReader, NodeWriter}, kvstore::KeyValueStore, nodeset::NodeSet, TrieError};
+
+pub mod history;
+pub use history::StateHistory;
+
+pub mod layertree;
+pub use layertree::{LayerTree, LayerReader, DiffLayer};
+
+// 账户trie节点的key前缀, 后接hex路径
+pub const ACCOUNT_TRIE_PREFIX: &[u8] = b"A";
+// 存储trie节点的key前缀, 后接owner和hex路径
+pub const STORAGE_TRIE_PREFIX: &[u8] = b"O";
+// 反向差异的key前缀, 后接8字节的状态id
+pub const STATE_HISTORY_PREFIX: &[u8] = b"H";
+// 当前状态: 最新状态id, 最早保留的历史id, 当前root
+pub const STATE_META_KEY: &[u8] = b"pathdb-meta";
+
+// 默认保留最近128个区块的历史
+pub const DEFAULT_HISTORY_LIMIT: u64 = 128;
+
+// 以(owner, hex路径)为key的节点库, 每个路径只保存最新版本, 更新时原地覆盖
+pub struct PathDB {
+    disk: Rc<dyn KeyValueStore>,
+    history_limit: u64,
+}
+
+// 状态元数据
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StateMeta {
+    pub id: u64,
+    pub tail: u64,
+    pub root: Hash,
+}
+
+impl PathDB {
+    pub fn new(disk: Rc<dyn KeyValueStore>) -> Self {
+        PathDB::with_history_limit(disk, DEFAULT_HISTORY_LIMIT)
+    }
+    pub fn with_history_limit(disk: Rc<dyn KeyValueStore>, history_limit: u64) -> Self {
+        PathDB { disk, history_limit }
+    }
+
+    // 提交一个区块的所有变更, 同时写入反向差异, parent为提交前的root
+    pub fn commit(&self, parent: Hash, root: Hash, sets: &[NodeSet]) -> Result<(), TrieError> {
+        let mut meta = self.meta()?;
+        if meta.root != parent && !(meta.root.is_empty_root() && parent.is_empty_root()) {
+            return Err(TrieError::BackendError(format!("parent root {} does not match current root {}", parent, meta.root)));
+        }
+        let mut history = StateHistory { parent, root, prev: Vec::new() };
+        for set in sets.iter() {
+            for (path, _) in set.iter() {
+                let prev = self.raw_node(set.owner, path)?.unwrap_or_default();
+                history.prev.push((set.owner, path.clone(), prev));
+            }
+        }
+        meta.id += 1;
+        self.disk.put(&history_key(meta.id), &history.encode())?;
+        for set in sets.iter() {
+            self.update(set)?;
+        }
+        meta.root = root;
+        // 超出保留数量的历史删除
+        while meta.id - meta.tail > self.history_limit {
+            meta.tail += 1;
+            self.disk.delete(&history_key(meta.tail))?;
+        }
+        self.write_meta(&meta)
+    }
+
+    // 按反向差异逐个回滚, 直到状态回到to_root
+    pub fn rollback(&self, to_root: Hash) -> Result<(), TrieError> {
+        let mut meta = self.meta()?;
+        // 先确认to_root在保留的历史中, 避免回滚一半
+        let mut id = meta.id;
+        let mut root = meta.root;
+        while root != to_root {
+            if id <= meta.tail {
+                return Err(TrieError::BackendError(format!("state {} is not in the retained history", to_root)));
+            }
+            root = self.history(id)?.parent;
+            id -= 1;
+        }
+        while meta.root != to_root {
+            let history = self.history(meta.id)?;
+            if history.root != meta.root {
+                return Err(TrieError::BackendError(format!("state history {} does not match root {}", meta.id, meta.root)));
+            }
+            for (owner, path, prev) in history.prev.iter().rev() {
+                let key = node_key(owner, path);
+                if prev.is_empty() {
+                    self.disk.delete(&key)?;
+                } else {
+                    self.disk.put(&key, prev)?;
+                }
+            }
+            self.disk.delete(&history_key(meta.id))?;
+            meta.id -= 1;
+            meta.root = history.parent;
+            self.write_meta(&meta)?;
+        }
+        Ok(())
+    }
+
+    pub fn meta(&self) -> Result<StateMeta, TrieError> {
+        let buf = match self.disk.get(STATE_META_KEY)? {
+            Some(buf) => buf,
+            None => return Ok(StateMeta { id: 0, tail: 0, root: Hash::empty_root_hash() }),
+        };
+        if buf.len() != 48 {
+            return Err(TrieError::DecodeError("invalid state meta".to_string()));
+        }
+        let mut id = [0_u8; 8];
+        let mut tail = [0_u8; 8];
+        id.copy_from_slice(&buf[..8]);
+        tail.copy_from_slice(&buf[8..16]);
+        Ok(StateMeta { id: u64::from_be_bytes(id), tail: u64::from_be_bytes(tail), root: to_hash(&buf[16..])? })
+    }
+
+    pub fn history(&self, id: u64) -> Result<StateHistory, TrieError> {
+        match self.disk.get(&history_key(id))? {
+            Some(buf) => StateHistory::decode(&buf),
+            None => Err(TrieError::BackendError(format!("state history {} not found", id))),
+        }
+    }
+
+    fn write_meta(&self, meta: &StateMeta) -> Result<(), TrieError> {
+        let mut buf = meta.id.to_be_bytes().to_vec();
+        buf.extend_from_slice(&meta.tail.to_be_bytes());
+        buf.extend_from_slice(meta.root.as_slice());
+        self.disk.put(STATE_META_KEY, &buf)
+    }
+
+    // 写入一次提交的节点, 删除的路径从磁盘移除
+    pub fn update(&self, set: &NodeSet) -> Result<(), TrieError> {
+        for (path, n) in set.iter() {
+            let key = node_key(&set.owner, path);
+            if n.is_deleted() {
+                self.disk.delete(&key)?;
+            } else {
+                self.disk.put(&key, &n.blob)?;
+            }
+        }
+        Ok(())
+    }
+
+    // 读取路径上当前保存的节点数据, 不校验hash
+    pub fn raw_node(&self, owner: Hash, path: &[u8]) -> Result<Option<Vec<u8>>, TrieError> {
+        self.disk.get(&node_key(&owner, path))
+    }
+}
+
+impl NodeWriter for PathDB {
+    fn write_nodes(&self, set: &NodeSet) -> Result<(), TrieError> {
+        self.update(set)
+    }
+}
+
+impl NodeReader for PathDB {
+    fn node(&self, owner: Hash, path: &[u8], hash: Hash) -> Result<Option<Vec<u8>>, TrieError> {
+        let blob = match self.raw_node(owner, path)? {
+            Some(blob) => blob,
+            None => return Ok(None),
+        };
+        // 路径上已是其他版本的节点, 视为不存在
+        if keccak256(&blob) != hash {
+            return Ok(None);
+        }
+        Ok(Some(blob))
+    }
+    fn unchecked_node(&self, owner: Hash, path: &[u8], _: Hash) -> Result<Option<Vec<u8>>, TrieError> {
+        self.raw_node(owner, path)
+    }
+}
+
+fn history_key(id: u64) -> Vec<u8> {
+    let mut key = STATE_HISTORY_PREFIX.to_vec();
+    key.extend_from_slice(&id.to_be_bytes());
+    key
+}
+
+pub(crate) fn node_key(owner: &Hash, path: &[u8]) -> Vec<u8> {
+    let mut key;
+    if *owner == Hash::default() {
+        key = ACCOUNT_TRIE_PREFIX.to_vec();
+    } else {
+        key = STORAGE_TRIE_PREFIX.to_vec();
+        key.extend_from_slice(owner.as_slice());
+    }
+    key.extend_from_slice(path);
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{integrity::verify_integrity, kvstore::MemoryStore, Trie, ID};
+
+    fn key(i: u32) -> Vec<u8> {
+        keccak256(&i.to_be_bytes()).to_vec()
+    }
+
+    // 在root之上执行一个区块: 写入或删除的key, 返回新root
+    fn block(db: &Rc<PathDB>, id: ID, parent: Hash, writes: &[(u32, Option<Vec<u8>>)]) -> Hash {
+        let owner = id.owner;
+        let mut t = Trie::open(id, db.clone()).unwrap();
+        for (i, v) in writes {
+            t.try_update(key(*i), v.clone()).unwrap();
+        }
+        let (root, set) = t.commit().unwrap();
+        assert_eq!(set.owner, owner);
+        db.commit(parent, root, &[set]).unwrap();
+        root
+    }
+
+    fn count_prefix(disk: &MemoryStore, prefix: &[u8]) -> usize {
+        disk.scan(prefix, usize::MAX).unwrap().iter().take_while(|(k, _)| k.starts_with(prefix)).count()
+    }
+
+    // 节点按路径保存, 每个路径只有最新版本, 删除的路径从磁盘移除
+    #[test]
+    fn overwrite_in_place() {
+        let disk = Rc::new(MemoryStore::new());
+        let db = Rc::new(PathDB::new(disk.clone()));
+        let writes: Vec<(u32, Option<Vec<u8>>)> = (0..300).map(|i| (i, Some(vec![i as u8; 40]))).collect();
+        let mut root = block(&db, ID::trie_id(Hash::empty_root_hash()), Hash::empty_root_hash(), &writes);
+        for round in 1..5u8 {
+            let writes: Vec<(u32, Option<Vec<u8>>)> = (0..300).step_by(3).map(|i| (i, Some(vec![round; 40]))).collect();
+            root = block(&db, ID::trie_id(root), root, &writes);
+            let report = verify_integrity(db.as_ref(), Hash::default(), root).unwrap();
+            assert!(report.is_ok());
+            assert_eq!(count_prefix(&disk, ACCOUNT_TRIE_PREFIX) as u64, report.nodes);
+        }
+        let deletes: Vec<(u32, Option<Vec<u8>>)> = (0..280).map(|i| (i, None)).collect();
+        root = block(&db, ID::trie_id(root), root, &deletes);
+        let report = verify_integrity(db.as_ref(), Hash::default(), root).unwrap();
+        assert_eq!(report.leaves, 20);
+        assert_eq!(count_prefix(&disk, ACCOUNT_TRIE_PREFIX) as u64, report.nodes);
+
+        let t = Trie::open(ID::trie_id(root), db.clone()).unwrap();
+        assert_eq!(t.get(&key(290)).unwrap(), Some(vec![290u32 as u8; 40]));
+        assert_eq!(t.get(&key(3)).unwrap(), None);
+        assert_eq!(db.meta().unwrap(), StateMeta { id: 6, tail: 0, root });
+    }
+
+    // 存储trie以owner区分, 与账户trie在同一个区块中提交, 节点互不覆盖
+    #[test]
+    fn storage_tries_by_owner() {
+        let disk = Rc::new(MemoryStore::new());
+        let db = Rc::new(PathDB::new(disk.clone()));
+        let owner = keccak256(b"account");
+        let mut storage = Trie::new(ID::storage_trie_id(Hash::default(), owner, Hash::empty_root_hash()));
+        let mut account = Trie::new(ID::trie_id(Hash::empty_root_hash()));
+        for i in 0..50 {
+            storage.try_update(key(i), Some(vec![1; 40])).unwrap();
+            account.try_update(key(i), Some(vec![2; 40])).unwrap();
+        }
+        let (storage_root, storage_set) = storage.commit().unwrap();
+        let (root, account_set) = account.commit().unwrap();
+        assert_eq!(storage_set.owner, owner);
+        db.commit(Hash::empty_root_hash(), root, &[storage_set, account_set]).unwrap();
+
+        assert_eq!(db.raw_node(owner, &[]).unwrap().map(|b| keccak256(&b)), Some(storage_root));
+        assert_eq!(db.raw_node(Hash::default(), &[]).unwrap().map(|b| keccak256(&b)), Some(root));
+        assert_eq!(count_prefix(&disk, STORAGE_TRIE_PREFIX), count_prefix(&disk, ACCOUNT_TRIE_PREFIX));
+        let t = Trie::open(ID::storage_trie_id(root, owner, storage_root), db.clone()).unwrap();
+        assert_eq!(t.get(&key(7)).unwrap(), Some(vec![1; 40]));
+        let t = Trie::open(ID::trie_id(root), db.clone()).unwrap();
+        assert_eq!(t.get(&key(7)).unwrap(), Some(vec![2; 40]));
+    }
+
+    #[test]
+    fn rejects_wrong_parent() {
+        let db = Rc::new(PathDB::new(Rc::new(MemoryStore::new())));
+        let root = block(&db, ID::trie_id(Hash::empty_root_hash()), Hash::empty_root_hash(), &[(1, Some(vec![1]))]);
+        let mut t = Trie::new(ID::trie_id(Hash::default()));
+        t.try_update(key(2), Some(vec![2])).unwrap();
+        let (other, set) = t.commit().unwrap();
+        assert!(matches!(db.commit(Hash::empty_root_hash(), other, &[set]), Err(TrieError::BackendError(_))));
+        assert_eq!(db.meta().unwrap().root, root);
+    }
+
+    fn dump(disk: &MemoryStore) -> Vec<(Vec<u8>, Vec<u8>)> {
+        disk.scan(&[], usize::MAX).unwrap()
+    }
+
+    // 逐块回滚后磁盘内容与当时完全一致
+    #[test]
+    fn rollback_restores_disk() {
+        let disk = Rc::new(MemoryStore::new());
+        let db = Rc::new(PathDB::new(disk.clone()));
+        let mut roots = vec![Hash::empty_root_hash()];
+        let mut dumps = vec![dump(&disk)];
+        for round in 0..4u32 {
+            let writes: Vec<(u32, Option<Vec<u8>>)> = (0..200)
+                .filter(|i| i % (round + 2) == 0)
+                .map(|i| (i, if round == 3 && i % 3 == 0 { None } else { Some(vec![round as u8; 1 + (i % 40) as usize]) }))
+                .collect();
+            let parent = roots[roots.len() - 1];
+            roots.push(block(&db, ID::trie_id(parent), parent, &writes));
+            dumps.push(dump(&disk));
+        }
+        db.rollback(roots[2]).unwrap();
+        assert_eq!(dump(&disk), dumps[2]);
+        assert_eq!(db.meta().unwrap(), StateMeta { id: 2, tail: 0, root: roots[2] });
+        let t = Trie::open(ID::trie_id(roots[2]), db.clone()).unwrap();
+        assert!(verify_integrity(db.as_ref(), Hash::default(), roots[2]).unwrap().is_ok());
+        assert_eq!(t.get(&key(6)).unwrap(), Some(vec![1; 7]));
+
+        // 回滚后可以在旧状态上继续提交
+        let root = block(&db, ID::trie_id(roots[2]), roots[2], &[(7, Some(vec![9]))]);
+        assert_eq!(db.meta().unwrap().id, 3);
+        db.rollback(roots[2]).unwrap();
+        assert_eq!(dump(&disk), dumps[2]);
+        assert!(db.rollback(root).is_err());
+
+        db.rollback(roots[0]).unwrap();
+        assert_eq!(count_prefix(&disk, ACCOUNT_TRIE_PREFIX), 0);
+        assert_eq!(count_prefix(&disk, STATE_HISTORY_PREFIX), 0);
+    }
+
+    // 只保留最近的历史, 超出范围的回滚在修改前就失败
+    #[test]
+    fn rollback_limited_history() {
+        let disk = Rc::new(MemoryStore::new());
+        let db = Rc::new(PathDB::with_history_limit(disk.clone(), 2));
+        let mut roots = vec![Hash::empty_root_hash()];
+        for round in 0..4u32 {
+            let parent = roots[roots.len() - 1];
+            let writes: Vec<(u32, Option<Vec<u8>>)> = (0..50).map(|i| (i * 4 + round, Some(vec![round as u8; 40]))).collect();
+            roots.push(block(&db, ID::trie_id(parent), parent, &writes));
+        }
+        assert_eq!(db.meta().unwrap(), StateMeta { id: 4, tail: 2, root: roots[4] });
+        assert_eq!(count_prefix(&disk, STATE_HISTORY_PREFIX), 2);
+        assert!(db.history(2).is_err());
+        assert_eq!(db.history(3).unwrap().parent, roots[2]);
+
+        let before = dump(&disk);
+        assert!(matches!(db.rollback(roots[1]), Err(TrieError::BackendError(_))));
+        assert!(db.rollback(keccak256(b"unknown")).is_err());
+        assert_eq!(dump(&disk), before);
+        db.rollback(roots[2]).unwrap();
+        assert!(verify_integrity(db.as_ref(), Hash::default(), roots[2]).unwrap().is_ok());
+    }
+
+    #[test]
+    fn history_round_trip() {
+        let h = StateHistory { parent: keccak256(b"a"), root: keccak256(b"b"), prev: vec![(Hash::default(), vec![], vec![1, 2]), (keccak256(b"o"), vec![3, 16], vec![])] };
+        let d = StateHistory::decode(&h.encode()).unwrap();
+        assert_eq!((d.parent, d.root, d.prev), (h.parent, h.root, h.prev));
+        assert!(StateHistory::decode(&[0xc1, 0x80]).is_err());
+    }
+}
@@ -0,0 +1,231 @@
+use std::{cell::{Cell, RefCell}, collections::BTreeMap, rc::Rc};
+
+use tokio::{sync::Notify, task::JoinHandle};
+
+use crate::{asyncreader::{fetch_nodes, AsyncNodeReader}, cache::NodeCache, common::{key_to_hex, Hash}, node::{Node, NodeType}, Trie, TrieError};
+
+// 预取状态, 由同一个预取的所有句柄共享
+struct PrefetchState {
+    cancelled: Cell<bool>,
+    done: Cell<bool>,
+    loaded: Cell<usize>,
+    notify: Notify,
+}
+
+// 把预先知道的key路径上的节点加载到trie的节点缓存, 之后执行时直接命中内存
+// 节点和缓存不能跨线程, 后台预取用spawn_local运行, 需要在tokio LocalSet中调用
+#[derive(Clone)]
+pub struct Prefetcher {
+    owner: Hash,
+    root: Rc<dyn Node>,
+    cache: Rc<RefCell<NodeCache>>,
+    keys: Rc<Vec<Vec<u8>>>,
+    state: Rc<PrefetchState>,
+}
+
+impl Prefetcher {
+    // 以trie当前的root为起点, 预取结果写入trie使用的节点缓存
+    pub fn new(t: &Trie, keys: Vec<Vec<u8>>) -> Self {
+        let state = PrefetchState { cancelled: Cell::new(false), done: Cell::new(false), loaded: Cell::new(0), notify: Notify::new() };
+        Prefetcher { owner: t.owner, root: Rc::clone(&t.root), cache: t.resolver.cache(), keys: Rc::new(keys), state: Rc::new(state) }
+    }
+
+    // 在后台任务中预取, 任务结果为加载的节点数
+    pub fn spawn<R: AsyncNodeReader + 'static>(&self, reader: Rc<R>) -> JoinHandle<Result<usize, TrieError>> {
+        let p = self.clone();
+        tokio::task::spawn_local(async move { p.run(reader.as_ref()).await })
+    }
+
+    // 在当前任务中预取, 返回加载的节点数
+    pub async fn run<R: AsyncNodeReader>(&self, reader: &R) -> Result<usize, TrieError> {
+        let ret = self.prefetch(reader).await;
+        self.state.done.set(true);
+        ret.map(|_| self.state.loaded.get())
+    }
+
+    // 取消预取, 正在进行的读取会被丢弃, 已加载的节点保留在缓存中
+    pub fn cancel(&self) {
+        self.state.cancelled.set(true);
+        self.state.notify.notify_waiters();
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.get()
+    }
+    pub fn is_done(&self) -> bool {
+        self.state.done.get()
+    }
+    // 目前为止从存储加载的节点数
+    pub fn loaded(&self) -> usize {
+        self.state.loaded.get()
+    }
+
+    // 所有key同时向下解析, 每轮把各key遇到的未缓存节点去重后并发读取
+    // 存储中不存在或读取失败的节点直接跳过, 只放弃经过它的key, 留给实际执行时报错
+    async fn prefetch<R: AsyncNodeReader>(&self, reader: &R) -> Result<(), TrieError> {
+        let mut pending: Vec<(Vec<u8>, usize, Rc<dyn Node>)> = self.keys.iter().map(|key| (key_to_hex(key), 0, Rc::clone(&self.root))).collect();
+        while !pending.is_empty() && !self.state.cancelled.get() {
+            let mut reqs = BTreeMap::new();
+            let mut waiting = Vec::new();
+            for (key, pos, n) in pending.drain(..) {
+                if let Some((pos, hash)) = self.descend(&key, pos, n)? {
+                    reqs.entry(hash).or_insert_with(|| key[..pos].to_vec());
+                    waiting.push((key, pos, hash));
+                }
+            }
+            if reqs.is_empty() {
+                break;
+            }
+            let reqs: Vec<(Vec<u8>, Hash)> = reqs.into_iter().map(|(hash, path)| (path, hash)).collect();
+            let results = tokio::select! {
+                results = fetch_nodes(reader, self.owner, &self.cache, &reqs) => results,
+                _ = self.state.notify.notified() => break,
+            };
+            let mut fetched = BTreeMap::new();
+            for ((_, hash), n) in reqs.iter().zip(results) {
+                if let Ok(Some((n, _))) = n {
+                    self.state.loaded.set(self.state.loaded.get() + 1);
+                    fetched.insert(*hash, n);
+                }
+            }
+            for (key, pos, hash) in waiting {
+                if let Some(n) = fetched.get(&hash) {
+                    pending.push((key, pos, Rc::clone(n)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // 沿key在内存和缓存中的节点向下走, 返回第一个未缓存节点的(路径长度, hash), 路径已完整时返回None
+    fn descend(&self, key: &[u8], mut pos: usize, mut n: Rc<dyn Node>) -> Result<Option<(usize, Hash)>, TrieError> {
+        loop {
+            match n.kind() {
+                NodeType::NullNode | NodeType::ValueNode => return Ok(None),
+                NodeType::ShortNode => {
+                    let sn = n.into_short_node()?;
+                    if !key[pos..].starts_with(&sn.key) {
+                        return Ok(None);
+                    }
+                    pos += sn.key.len();
+                    n = sn.val;
+                },
+                NodeType::FullNode => {
+                    if pos >= key.len() {
+                        return Ok(None);
+                    }
+                    match &n.into_full_node()?.children[key[pos] as usize] {
+                        Some(child) => n = Rc::clone(child),
+                        None => return Ok(None),
+                    }
+                    pos += 1;
+                },
+                NodeType::HashNode => {
+                    let hash = Hash::from(n.into_hash_node()?.0);
+                    // 探测不计入缓存的命中统计
+                    let cached = self.cache.borrow().peek(&hash);
+                    match cached {
+                        Some(cached) => n = cached,
+                        None => return Ok(Some((pos, hash))),
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{common::keccak256, database::{MemoryDB, NodeReader}, ID};
+
+    struct Store(MemoryDB);
+
+    impl AsyncNodeReader for Store {
+        async fn get_node(&self, owner: Hash, path: &[u8], hash: Hash) -> Result<Option<Vec<u8>>, TrieError> {
+            tokio::task::yield_now().await;
+            self.0.node(owner, path, hash)
+        }
+    }
+
+    fn key(i: u32) -> Vec<u8> {
+        keccak256(&i.to_be_bytes()).to_vec()
+    }
+
+    async fn open() -> (Trie, Rc<Store>) {
+        let mut t = Trie::new(ID::trie_id(Hash::default()));
+        for i in 0..500u32 {
+            t.try_update(key(i), Some(vec![i as u8; 1 + (i % 40) as usize])).unwrap();
+        }
+        let (root, set) = t.commit().unwrap();
+        let db = MemoryDB::new();
+        db.update(&set);
+        let store = Rc::new(Store(db));
+        let t = Trie::open_async(ID::trie_id(root), store.as_ref()).await.unwrap();
+        t.resolver.cache().borrow_mut().reset_stats();
+        (t, store)
+    }
+
+    // 预取后查找全部命中缓存, 且预取本身不计入命中统计
+    #[tokio::test]
+    async fn warms_cache() {
+        let (t, store) = open().await;
+        let keys: Vec<Vec<u8>> = (0..500u32).step_by(3).map(key).collect();
+        let p = Prefetcher::new(&t, keys.clone());
+        let loaded = p.run(store.as_ref()).await.unwrap();
+        assert!(p.is_done() && loaded > 0 && loaded == p.loaded());
+        let stats = t.cache_stats();
+        assert_eq!((stats.hits, stats.misses), (0, 0));
+
+        // 再次预取不需要读取
+        assert_eq!(Prefetcher::new(&t, keys.clone()).run(store.as_ref()).await.unwrap(), 0);
+        let stats = t.cache_stats();
+        assert_eq!((stats.hits, stats.misses), (0, 0));
+
+        for (i, k) in (0..500u32).step_by(3).zip(keys.iter()) {
+            assert_eq!(t.get(k).unwrap(), Some(vec![i as u8; 1 + (i % 40) as usize]));
+        }
+        assert_eq!(t.cache_stats().misses, 0);
+    }
+
+    // 读取路径以nibble开头的节点时失败的存储
+    struct Failing<'a>(&'a Store, u8);
+
+    impl AsyncNodeReader for Failing<'_> {
+        async fn get_node(&self, owner: Hash, path: &[u8], hash: Hash) -> Result<Option<Vec<u8>>, TrieError> {
+            if path.first() == Some(&self.1) {
+                return Err(TrieError::missing(path, hash));
+            }
+            self.0.get_node(owner, path, hash).await
+        }
+    }
+
+    // 读取失败只影响经过该节点的key, 其余key照常预取
+    #[tokio::test]
+    async fn read_errors_skip_keys() {
+        let (t, store) = open().await;
+        let keys: Vec<Vec<u8>> = (0..500u32).map(key).collect();
+        let nibble = keys[0][0] >> 4;
+        let p = Prefetcher::new(&t, keys.clone());
+        assert!(p.run(&Failing(store.as_ref(), nibble)).await.unwrap() > 0);
+        assert!(p.is_done());
+        for k in keys.iter().filter(|k| k[0] >> 4 != nibble) {
+            t.get(k).unwrap();
+        }
+        assert_eq!(t.cache_stats().misses, 0);
+        // 失败的子树没有预取, 没有同步存储的trie读不到
+        assert!(t.get(&keys[0]).is_err());
+    }
+
+    #[tokio::test]
+    async fn cancel() {
+        let local = tokio::task::LocalSet::new();
+        local.run_until(async {
+            let (t, store) = open().await;
+            let p = Prefetcher::new(&t, (0..500u32).map(key).collect());
+            p.cancel();
+            let loaded = p.spawn(store).await.unwrap().unwrap();
+            assert!(p.is_cancelled() && p.is_done());
+            assert_eq!(loaded, 0);
+        }).await;
+    }
+}
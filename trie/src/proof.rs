@@ -0,0 +1,419 @@
+use std::{collections::{BTreeMap, BTreeSet}, rc::Rc};
+
+use crate::{common::{compact_to_hex, has_term, keccak256, key_to_hex, to_hash, Hash}, hasher::Hasher, json::{from_hex, to_hex, Json}, node::NodeType, rlp, writer::EncodeBuffer, Trie, TrieError};
+
+// 多个key共用的证明, 节点按先序排列, 每个节点只出现一次
+// 子节点也在证明中时, 父节点里该子节点的hash替换为空列表0xc0, 验证时由子节点重新计算
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MultiProof {
+    pub nodes: Vec<Vec<u8>>,
+}
+
+impl MultiProof {
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+    // 证明的总字节数
+    pub fn size(&self) -> usize {
+        self.nodes.iter().map(|n| n.len()).sum()
+    }
+}
+
+impl Trie {
+    // key路径上所有存储节点的编码, 从root开始, key不存在时证明其不存在
+    pub fn prove(&mut self, key: &[u8]) -> Result<Vec<Vec<u8>>, TrieError> {
+        self.hash()?;
+        let mut proof = Vec::new();
+        self.prove_path(&key_to_hex(key), &mut |_, blob| proof.push(blob))?;
+        Ok(proof)
+    }
+
+    // 为多个key生成一个证明, 共享的节点只保留一份, 可由证明中的节点重新计算的hash省略
+    pub fn prove_many(&mut self, keys: &[&[u8]]) -> Result<MultiProof, TrieError> {
+        let root = self.hash()?;
+        let mut blobs = BTreeMap::new();
+        for key in keys {
+            self.prove_path(&key_to_hex(key), &mut |hash, blob| {
+                blobs.entry(hash).or_insert(blob);
+            })?;
+        }
+        let mut proof = MultiProof::default();
+        if !blobs.is_empty() {
+            let mut emitted = BTreeSet::new();
+            compact_node(root, &blobs, &mut emitted, &mut proof.nodes)?;
+        }
+        Ok(proof)
+    }
+
+    fn prove_path(&self, key: &[u8], f: &mut dyn FnMut(Hash, Vec<u8>)) -> Result<(), TrieError> {
+        let mut h = Hasher::new(false);
+        let mut n = Rc::clone(&self.root);
+        let mut pos = 0;
+        loop {
+            // 有hash的节点单独存储, 没有hash的节点内嵌在父节点中
+            if let (Some(hash), _) = n.cache() {
+                f(Hash::from(hash.0), h.encode_node(Rc::clone(&n))?);
+            }
+            match n.kind() {
+                NodeType::HashNode => {
+                    let hn = n.into_hash_node()?;
+                    n = self.resolver.resolve(Hash::from(hn.0), &key[..pos])?;
+                },
+                NodeType::ShortNode => {
+                    let sn = n.into_short_node()?;
+                    if !key[pos..].starts_with(&sn.key) {
+                        return Ok(());
+                    }
+                    pos += sn.key.len();
+                    n = sn.val;
+                },
+                NodeType::FullNode => {
+                    if pos >= key.len() {
+                        return Err(TrieError::invalid("full node at end of key"));
+                    }
+                    let f_n = n.into_full_node()?;
+                    match &f_n.children[key[pos] as usize] {
+                        Some(child) => n = Rc::clone(child),
+                        None => return Ok(()),
+                    }
+                    pos += 1;
+                },
+                NodeType::ValueNode | NodeType::NullNode => return Ok(()),
+            }
+        }
+    }
+}
+
+// 按先序输出节点, 已输出过的子节点保留hash
+fn compact_node(hash: Hash, blobs: &BTreeMap<Hash, Vec<u8>>, emitted: &mut BTreeSet<Hash>, out: &mut Vec<Vec<u8>>) -> Result<(), TrieError> {
+    let blob = match blobs.get(&hash) {
+        Some(blob) => blob,
+        None => return Err(TrieError::invalid("proof node not collected")),
+    };
+    emitted.insert(hash);
+    let index = out.len();
+    out.push(Vec::new());
+    let mut children = Vec::new();
+    let compacted = map_refs(blob, &mut |item| {
+        if item.len() != 33 || item[0] != 0xa0 {
+            return Ok(None);
+        }
+        let child = to_hash(&item[1..])?;
+        if !blobs.contains_key(&child) || emitted.contains(&child) {
+            return Ok(None);
+        }
+        // 先标记, 防止同一节点下相同的子树输出两次
+        emitted.insert(child);
+        children.push(child);
+        Ok(Some(vec![0xc0]))
+    })?;
+    out[index] = compacted;
+    for child in children {
+        emitted.remove(&child);
+        compact_node(child, blobs, emitted, out)?;
+    }
+    Ok(())
+}
+
+// 处理子节点引用的原始RLP数据, 返回Some时替换
+type RefMapper<'a> = dyn FnMut(&[u8]) -> Result<Option<Vec<u8>>, TrieError> + 'a;
+
+// 依次处理节点中引用子节点的位置, f返回Some时替换该位置的原始RLP数据
+fn map_refs(blob: &[u8], f: &mut RefMapper<'_>) -> Result<Vec<u8>, TrieError> {
+    let (mut elems, _) = rlp::split_list(blob)?;
+    let count = rlp::count_values(elems)?;
+    let refs = match count {
+        17 => 16,
+        2 => {
+            let (kbuf, _) = rlp::split_string(elems)?;
+            if has_term(&compact_to_hex(kbuf)) { 0 } else { 2 }
+        },
+        c => return Err(TrieError::DecodeError(format!("invalid number of list elements: {}", c))),
+    };
+    let mut w = EncodeBuffer::new();
+    let offset = w.list();
+    for i in 0..count {
+        let (_, _, rest) = rlp::split(elems)?;
+        let item = &elems[..elems.len() - rest.len()];
+        // shortNode只有第二项是子节点引用
+        let is_ref = if count == 2 { i == 1 && refs == 2 } else { i < refs };
+        let replaced = if is_ref { f(item)? } else { None };
+        for b in replaced.as_deref().unwrap_or(item) {
+            w.write(*b);
+        }
+        elems = rest;
+    }
+    w.list_end(offset);
+    Ok(w.encode_bytes())
+}
+
+// 还原先序排列的证明节点, 返回节点hash
+fn expand_node(nodes: &[Vec<u8>], next: &mut usize, out: &mut Vec<Vec<u8>>) -> Result<Hash, TrieError> {
+    let blob = match nodes.get(*next) {
+        Some(blob) => blob,
+        None => return Err(TrieError::InvalidProof("proof ended before all references were resolved".to_string())),
+    };
+    *next += 1;
+    let expanded = map_refs(blob, &mut |item| {
+        if item != [0xc0] {
+            return Ok(None);
+        }
+        let child = expand_node(nodes, next, out)?;
+        let mut r = vec![0xa0];
+        r.extend_from_slice(child.as_slice());
+        Ok(Some(r))
+    })?;
+    let hash = keccak256(&expanded);
+    out.push(expanded);
+    Ok(hash)
+}
+
+// 用prove生成的证明验证key, 返回key的值, key不存在时返回None
+pub fn verify_proof(root: Hash, key: &[u8], proof: &[Vec<u8>]) -> Result<Option<Vec<u8>>, TrieError> {
+    let ret = verify_keys(root, &[key], proof)?;
+    Ok(ret.into_iter().next().flatten())
+}
+
+// 验证多key证明, 按keys的顺序返回每个key的值或不存在
+pub fn verify_multiproof(root: Hash, keys: &[&[u8]], proof: &MultiProof) -> Result<Vec<Option<Vec<u8>>>, TrieError> {
+    let mut nodes = Vec::with_capacity(proof.nodes.len());
+    if !proof.nodes.is_empty() {
+        let mut next = 0;
+        let hash = expand_node(&proof.nodes, &mut next, &mut nodes)?;
+        if hash != root {
+            return Err(TrieError::InvalidProof(format!("proof root {} does not match {}", hash, root)));
+        }
+        if next != proof.nodes.len() {
+            return Err(TrieError::InvalidProof("unused nodes in proof".to_string()));
+        }
+    }
+    verify_keys(root, keys, &nodes)
+}
+
+fn verify_keys(root: Hash, keys: &[&[u8]], nodes: &[Vec<u8>]) -> Result<Vec<Option<Vec<u8>>>, TrieError> {
+    let ret = Trie::from_witness(root, nodes).and_then(|t| t.get_many(keys));
+    match ret {
+        Err(TrieError::MissingNode { path, hash }) => Err(TrieError::InvalidProof(format!("missing node {} at path {}", hash, hex::encode(path)))),
+        ret => ret,
+    }
+}
+
+// 二进制证明格式的版本
+pub const PROOF_BINARY_VERSION: u8 = 1;
+
+// 证明的序列化格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProofFormat {
+    // 节点的RLP列表, 与以太坊的NodeList兼容
+    Rlp,
+    // 0x前缀hex字符串的JSON数组
+    Json,
+    // 版本号, 节点数, 每个节点带长度前缀, 最后4字节为keccak256校验和
+    Binary,
+}
+
+pub fn encode_proof(nodes: &[Vec<u8>], format: ProofFormat) -> Vec<u8> {
+    match format {
+        ProofFormat::Rlp => {
+            let mut w = EncodeBuffer::new();
+            let offset = w.list();
+            // 节点本身是RLP列表, 原样写入
+            for node in nodes {
+                for b in node {
+                    w.write(*b);
+                }
+            }
+            w.list_end(offset);
+            w.encode_bytes()
+        },
+        ProofFormat::Json => {
+            Json::Arr(nodes.iter().map(|n| Json::Str(to_hex(n))).collect()).encode().into_bytes()
+        },
+        ProofFormat::Binary => {
+            let mut out = vec![PROOF_BINARY_VERSION];
+            out.extend((nodes.len() as u32).to_be_bytes());
+            for node in nodes {
+                out.extend((node.len() as u32).to_be_bytes());
+                out.extend(node);
+            }
+            let sum = keccak256(&out);
+            out.extend(&sum[..4]);
+            out
+        },
+    }
+}
+
+pub fn decode_proof(buf: &[u8], format: ProofFormat) -> Result<Vec<Vec<u8>>, TrieError> {
+    match format {
+        ProofFormat::Rlp => {
+            let (mut elems, rest) = rlp::split_list(buf)?;
+            if !rest.is_empty() {
+                return Err(TrieError::DecodeError("trailing bytes after proof".to_string()));
+            }
+            let mut nodes = Vec::new();
+            while !elems.is_empty() {
+                let (_, rest) = rlp::split_list(elems)?;
+                nodes.push(elems[..elems.len() - rest.len()].to_vec());
+                elems = rest;
+            }
+            Ok(nodes)
+        },
+        ProofFormat::Json => {
+            let s = match std::str::from_utf8(buf) {
+                Ok(s) => s,
+                Err(_) => return Err(TrieError::DecodeError("proof json is not utf-8".to_string())),
+            };
+            Json::parse(s)?.as_arr()?.iter().map(|n| from_hex(n.as_str()?)).collect()
+        },
+        ProofFormat::Binary => {
+            if buf.len() < 9 {
+                return Err(TrieError::DecodeError("binary proof too short".to_string()));
+            }
+            if buf[0] != PROOF_BINARY_VERSION {
+                return Err(TrieError::DecodeError(format!("unsupported proof version {}", buf[0])));
+            }
+            let (body, sum) = buf.split_at(buf.len() - 4);
+            if keccak256(body)[..4] != *sum {
+                return Err(TrieError::DecodeError("proof checksum mismatch".to_string()));
+            }
+            let count = be_u32(&body[1..5]);
+            let mut rest = &body[5..];
+            let mut nodes = Vec::with_capacity(count.min(1024));
+            for _ in 0..count {
+                if rest.len() < 4 {
+                    return Err(TrieError::DecodeError("binary proof truncated".to_string()));
+                }
+                let size = be_u32(&rest[..4]);
+                if rest.len() - 4 < size {
+                    return Err(TrieError::DecodeError("binary proof truncated".to_string()));
+                }
+                nodes.push(rest[4..4 + size].to_vec());
+                rest = &rest[4 + size..];
+            }
+            if !rest.is_empty() {
+                return Err(TrieError::DecodeError("trailing bytes after proof".to_string()));
+            }
+            Ok(nodes)
+        },
+    }
+}
+
+fn be_u32(b: &[u8]) -> usize {
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ID;
+
+    fn sample() -> (Hash, Vec<Vec<u8>>) {
+        let mut t = Trie::new(ID::trie_id(Hash::default()));
+        for i in 0..500u32 {
+            t.try_update(i.to_be_bytes().to_vec(), Some(vec![i as u8; 1 + (i % 40) as usize])).unwrap();
+        }
+        let root = t.hash().unwrap();
+        (root, t.prove(&77u32.to_be_bytes()).unwrap())
+    }
+
+    #[test]
+    fn round_trip() {
+        let (root, proof) = sample();
+        for format in [ProofFormat::Rlp, ProofFormat::Json, ProofFormat::Binary] {
+            let decoded = decode_proof(&encode_proof(&proof, format), format).unwrap();
+            assert_eq!(decoded, proof);
+            assert_eq!(verify_proof(root, &77u32.to_be_bytes(), &decoded).unwrap(), Some(vec![77; 38]));
+            assert_eq!(decode_proof(&encode_proof(&[], format), format).unwrap(), Vec::<Vec<u8>>::new());
+        }
+    }
+
+    #[test]
+    fn json_is_hex_array() {
+        let nodes = vec![vec![0xc1, 0x80], vec![0xc2, 0x01, 0x02]];
+        let json = encode_proof(&nodes, ProofFormat::Json);
+        assert_eq!(String::from_utf8(json).unwrap(), "[\"0xc180\",\"0xc20102\"]");
+        assert_eq!(decode_proof(b" [ \"0xc180\" , \"0xc20102\" ] ", ProofFormat::Json).unwrap(), nodes);
+    }
+
+    #[test]
+    fn binary_rejects_corruption() {
+        let (_, proof) = sample();
+        let mut buf = encode_proof(&proof, ProofFormat::Binary);
+        buf[10] ^= 1;
+        assert!(decode_proof(&buf, ProofFormat::Binary).is_err());
+        let mut buf = encode_proof(&proof, ProofFormat::Binary);
+        buf[0] = 2;
+        assert!(decode_proof(&buf, ProofFormat::Binary).is_err());
+        let buf = encode_proof(&proof, ProofFormat::Binary);
+        assert!(decode_proof(&buf[..buf.len() - 1], ProofFormat::Binary).is_err());
+    }
+
+    #[test]
+    fn rlp_rejects_non_list_nodes() {
+        assert!(decode_proof(&[0xc2, 0x81, 0x80], ProofFormat::Rlp).is_err());
+        assert!(decode_proof(&[0xc1, 0xc0, 0x00], ProofFormat::Rlp).is_err());
+    }
+
+    fn hashed() -> Trie {
+        let mut t = Trie::new(ID::trie_id(Hash::default()));
+        for i in 0..500u32 {
+            t.try_update(keccak256(&i.to_be_bytes()).to_vec(), Some(vec![i as u8; 1 + (i % 40) as usize])).unwrap();
+        }
+        t
+    }
+
+    // 多个key共用一个证明, 存在的key返回值, 不存在的返回None, 比分别证明小
+    #[test]
+    fn multiproof() {
+        let mut t = hashed();
+        let root = t.hash().unwrap();
+        let keys: Vec<Vec<u8>> = (0..600u32).step_by(37).map(|i| keccak256(&i.to_be_bytes()).to_vec()).collect();
+        let refs: Vec<&[u8]> = keys.iter().map(|k| k.as_slice()).collect();
+        let proof = t.prove_many(&refs).unwrap();
+        let values = verify_multiproof(root, &refs, &proof).unwrap();
+        let mut separate = 0;
+        for (k, v) in keys.iter().zip(values) {
+            assert_eq!(v, t.get(k).unwrap());
+            let single = t.prove(k).unwrap();
+            assert_eq!(verify_proof(root, k, &single).unwrap(), v);
+            separate += single.iter().map(|n| n.len()).sum::<usize>();
+        }
+        let size: usize = proof.nodes.iter().map(|n| n.len()).sum();
+        assert!(size < separate / 2);
+        // 每个节点只出现一次
+        let mut hashes: Vec<Hash> = proof.nodes.iter().map(|n| keccak256(n)).collect();
+        hashes.sort();
+        hashes.dedup();
+        assert_eq!(hashes.len(), proof.len());
+    }
+
+    #[test]
+    fn multiproof_rejects_tampering() {
+        let mut t = hashed();
+        let root = t.hash().unwrap();
+        let keys: Vec<Vec<u8>> = (0..5u32).map(|i| keccak256(&i.to_be_bytes()).to_vec()).collect();
+        let refs: Vec<&[u8]> = keys.iter().map(|k| k.as_slice()).collect();
+        let proof = t.prove_many(&refs).unwrap();
+        assert!(verify_multiproof(keccak256(b"x"), &refs, &proof).is_err());
+
+        let mut truncated = proof.clone();
+        truncated.nodes.pop();
+        assert!(verify_multiproof(root, &refs, &truncated).is_err());
+
+        let mut changed = proof.clone();
+        let last = changed.nodes.len() - 1;
+        let n = changed.nodes[last].len();
+        changed.nodes[last][n - 1] ^= 1;
+        assert!(verify_multiproof(root, &refs, &changed).is_err());
+
+        // 证明之外的key无法判断
+        let other = keccak256(&1000u32.to_be_bytes());
+        let mut with_other = refs.clone();
+        with_other.push(other.as_slice());
+        assert!(verify_multiproof(root, &with_other, &proof).is_err());
+        assert_eq!(verify_multiproof(Hash::empty_root_hash(), &refs, &MultiProof::default()).unwrap(), vec![None; 5]);
+    }
+}
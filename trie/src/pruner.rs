@@ -0,0 +1,438 @@
+use std::{collections::BTreeSet, rc::Rc};
+
+use crate::{common::{keccak256, to_hash, Hash}, hashdb::{decode_count, node_key, refcount_key, NODE_PREFIX}, kvstore::KeyValueStore, node::child_hashes, TrieError};
+
+// 中断后用于恢复的状态, 保存在被清理的存储中
+pub const PRUNER_BLOOM_KEY: &[u8] = b"pruner-bloom";
+pub const PRUNER_CURSOR_KEY: &[u8] = b"pruner-cursor";
+// 标记阶段中断时的遍历状态和部分布隆过滤器
+pub const PRUNER_MARK_KEY: &[u8] = b"pruner-mark";
+// 累计的进度计数, 恢复后继续累加
+pub const PRUNER_PROGRESS_KEY: &[u8] = b"pruner-progress";
+
+// 每处理多少个节点报告一次进度
+const PROGRESS_INTERVAL: u64 = 1000;
+const SWEEP_BATCH: usize = 1000;
+
+// 布隆过滤器, 节点hash本身已是均匀分布, 直接取其中的两段做双重hash
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    k: u32,
+}
+
+impl BloomFilter {
+    // 按预计元素数量和误判率确定大小
+    pub fn new(items: u64, fp_rate: f64) -> Self {
+        let items = items.max(1) as f64;
+        let m = (-(items * fp_rate.ln()) / (2_f64.ln() * 2_f64.ln())).ceil().max(64.0) as u64;
+        let k = ((m as f64 / items) * 2_f64.ln()).round().clamp(1.0, 30.0) as u32;
+        BloomFilter { bits: vec![0; m.div_ceil(64) as usize], k }
+    }
+    pub fn insert(&mut self, hash: &Hash) {
+        for idx in self.indexes(hash) {
+            self.bits[idx / 64] |= 1 << (idx % 64);
+        }
+    }
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.indexes(hash).all(|idx| self.bits[idx / 64] & (1 << (idx % 64)) != 0)
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = self.k.to_be_bytes().to_vec();
+        for v in self.bits.iter() {
+            buf.extend_from_slice(&v.to_be_bytes());
+        }
+        buf
+    }
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, TrieError> {
+        if buf.len() < 12 || !(buf.len() - 4).is_multiple_of(8) {
+            return Err(TrieError::DecodeError("invalid bloom filter data".to_string()));
+        }
+        let mut k = [0_u8; 4];
+        k.copy_from_slice(&buf[..4]);
+        let bits = buf[4..].chunks(8).map(|c| {
+            let mut v = [0_u8; 8];
+            v.copy_from_slice(c);
+            u64::from_be_bytes(v)
+        }).collect();
+        Ok(BloomFilter { bits, k: u32::from_be_bytes(k) })
+    }
+
+    fn indexes(&self, hash: &Hash) -> impl Iterator<Item = usize> {
+        let mut a = [0_u8; 8];
+        let mut b = [0_u8; 8];
+        a.copy_from_slice(&hash[..8]);
+        b.copy_from_slice(&hash[8..16]);
+        let (h1, h2) = (u64::from_be_bytes(a), u64::from_be_bytes(b));
+        let m = (self.bits.len() * 64) as u64;
+        (0..self.k as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % m) as usize)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PrunePhase {
+    Marking,
+    Sweeping,
+    Done,
+}
+
+// 计数是整个清理过程的累计值, 包括中断前完成的部分
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PruneProgress {
+    pub phase: PrunePhase,
+    // 标记的存活节点数
+    pub marked: u64,
+    // 清理阶段扫描过的key数
+    pub swept: u64,
+    // 删除的节点数
+    pub deleted: u64,
+    // 删除的字节数
+    pub reclaimed_bytes: u64,
+}
+
+impl PruneProgress {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(32);
+        for v in [self.marked, self.swept, self.deleted, self.reclaimed_bytes] {
+            buf.extend(v.to_be_bytes());
+        }
+        buf
+    }
+    fn decode(buf: &[u8]) -> Result<Self, TrieError> {
+        if buf.len() != 32 {
+            return Err(TrieError::DecodeError("invalid prune progress".to_string()));
+        }
+        let num = |i: usize| read_u64(&buf[i * 8..]);
+        Ok(PruneProgress { phase: PrunePhase::Marking, marked: num(0), swept: num(1), deleted: num(2), reclaimed_bytes: num(3) })
+    }
+}
+
+// 离线清理: 从保留的root出发把可达节点记入布隆过滤器, 再删除存储中不在过滤器内的节点
+// 布隆过滤器误判只会少删, 不会误删存活节点
+pub struct Pruner {
+    store: Rc<dyn KeyValueStore>,
+    roots: Vec<Hash>,
+    expected_nodes: u64,
+    progress: PruneProgress,
+}
+
+impl Pruner {
+    // expected_nodes为预计存活节点数, 用于确定布隆过滤器大小
+    pub fn new(store: Rc<dyn KeyValueStore>, roots: Vec<Hash>, expected_nodes: u64) -> Self {
+        let progress = PruneProgress { phase: PrunePhase::Marking, marked: 0, swept: 0, deleted: 0, reclaimed_bytes: 0 };
+        Pruner { store, roots, expected_nodes, progress }
+    }
+
+    // 执行清理, on_progress返回false时中断, 标记的遍历状态、清理位置和计数会保存下来, 再次调用时继续
+    pub fn prune(&mut self, on_progress: &mut dyn FnMut(&PruneProgress) -> bool) -> Result<PruneProgress, TrieError> {
+        if let Some(buf) = self.store.get(PRUNER_PROGRESS_KEY)? {
+            self.progress = PruneProgress::decode(&buf)?;
+        }
+        let bloom = match self.store.get(PRUNER_BLOOM_KEY)? {
+            // 上次已完成标记, 直接继续清理
+            Some(buf) => BloomFilter::from_bytes(&buf)?,
+            None => {
+                self.progress.phase = PrunePhase::Marking;
+                let bloom = match self.mark(on_progress)? {
+                    Some(bloom) => bloom,
+                    None => return Ok(self.progress),
+                };
+                self.store.put(PRUNER_BLOOM_KEY, &bloom.to_bytes())?;
+                self.store.delete(PRUNER_MARK_KEY)?;
+                self.store.delete(PRUNER_CURSOR_KEY)?;
+                self.store.put(PRUNER_PROGRESS_KEY, &self.progress.encode())?;
+                bloom
+            },
+        };
+        self.progress.phase = PrunePhase::Sweeping;
+        if !self.sweep(&bloom, on_progress)? {
+            return Ok(self.progress);
+        }
+        self.store.delete(PRUNER_BLOOM_KEY)?;
+        self.store.delete(PRUNER_CURSOR_KEY)?;
+        self.store.delete(PRUNER_PROGRESS_KEY)?;
+        self.progress.phase = PrunePhase::Done;
+        on_progress(&self.progress);
+        Ok(self.progress)
+    }
+
+    // 依次遍历每个root, 多个root共享的节点只访问一次
+    // 中断时保存当前root序号、待访问的(路径, hash)栈、已访问的hash和布隆过滤器
+    fn mark(&mut self, on_progress: &mut dyn FnMut(&PruneProgress) -> bool) -> Result<Option<BloomFilter>, TrieError> {
+        let (mut bloom, mut next_root, mut stack, mut visited) = match self.store.get(PRUNER_MARK_KEY)? {
+            Some(buf) => decode_mark(&buf)?,
+            None => (BloomFilter::new(self.expected_nodes, 0.0005), 0, Vec::new(), BTreeSet::new()),
+        };
+        loop {
+            let (path, hash) = match stack.pop() {
+                Some(item) => item,
+                None => {
+                    if next_root >= self.roots.len() {
+                        return Ok(Some(bloom));
+                    }
+                    next_root += 1;
+                    let root = self.roots[next_root - 1];
+                    if root.is_empty_root() {
+                        continue;
+                    }
+                    (Vec::new(), root)
+                },
+            };
+            // 布隆过滤器有误判, 用精确的集合判断是否访问过
+            if visited.contains(&hash) {
+                continue;
+            }
+            let blob = match self.store.get(&node_key(&hash))? {
+                Some(blob) => blob,
+                // 保留的trie不完整时不能清理
+                None => return Err(TrieError::missing(&path, hash)),
+            };
+            bloom.insert(&hash);
+            visited.insert(hash);
+            self.progress.marked += 1;
+            for (child_path, child) in child_hashes(&blob)? {
+                let mut next = path.clone();
+                next.extend(child_path);
+                stack.push((next, child));
+            }
+            if self.progress.marked.is_multiple_of(PROGRESS_INTERVAL) && !on_progress(&self.progress) {
+                self.store.put(PRUNER_MARK_KEY, &encode_mark(&bloom, next_root, &stack, &visited))?;
+                self.store.put(PRUNER_PROGRESS_KEY, &self.progress.encode())?;
+                return Ok(None);
+            }
+        }
+    }
+
+    fn sweep(&mut self, bloom: &BloomFilter, on_progress: &mut dyn FnMut(&PruneProgress) -> bool) -> Result<bool, TrieError> {
+        // 只扫描HashDB的节点key范围
+        let mut cursor = self.store.get(PRUNER_CURSOR_KEY)?.unwrap_or_else(|| NODE_PREFIX.to_vec());
+        loop {
+            let batch = self.store.scan(&cursor, SWEEP_BATCH)?;
+            if batch.is_empty() {
+                return Ok(true);
+            }
+            for (key, value) in batch {
+                if !key.starts_with(NODE_PREFIX) {
+                    return Ok(true);
+                }
+                cursor = key.clone();
+                cursor.push(0);
+                self.progress.swept += 1;
+                // 只处理以自身hash为key的节点数据
+                let hash = &key[NODE_PREFIX.len()..];
+                if hash.len() == 32 && *keccak256(&value) == hash {
+                    let hash = to_hash(hash)?;
+                    if !bloom.contains(&hash) {
+                        self.store.delete(&key)?;
+                        self.store.delete(&refcount_key(&hash))?;
+                        self.release(&value)?;
+                        self.progress.deleted += 1;
+                        self.progress.reclaimed_bytes += (key.len() + value.len()) as u64;
+                    }
+                }
+                if self.progress.swept.is_multiple_of(PROGRESS_INTERVAL) {
+                    self.store.put(PRUNER_CURSOR_KEY, &cursor)?;
+                    self.store.put(PRUNER_PROGRESS_KEY, &self.progress.encode())?;
+                    if !on_progress(&self.progress) {
+                        return Ok(false);
+                    }
+                }
+            }
+        }
+    }
+
+    // 删除的节点不再引用它的子节点, 存活的子节点引用计数减1, 已删除的子节点没有计数可减
+    fn release(&self, blob: &[u8]) -> Result<(), TrieError> {
+        for (_, child) in child_hashes(blob)? {
+            let key = refcount_key(&child);
+            if let Some(v) = self.store.get(&key)? {
+                self.store.put(&key, &decode_count(&v).saturating_sub(1).to_be_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn read_u64(buf: &[u8]) -> u64 {
+    let mut b = [0_u8; 8];
+    b.copy_from_slice(&buf[..8]);
+    u64::from_be_bytes(b)
+}
+
+// [下一个root序号 u64][栈长度 u64]{[路径长度 u64][路径][hash]}...[已访问数 u64]{[hash]}...[布隆过滤器]
+fn encode_mark(bloom: &BloomFilter, next_root: usize, stack: &[(Vec<u8>, Hash)], visited: &BTreeSet<Hash>) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.extend((next_root as u64).to_be_bytes());
+    buf.extend((stack.len() as u64).to_be_bytes());
+    for (path, hash) in stack {
+        buf.extend((path.len() as u64).to_be_bytes());
+        buf.extend(path);
+        buf.extend(hash.as_slice());
+    }
+    buf.extend((visited.len() as u64).to_be_bytes());
+    for hash in visited {
+        buf.extend(hash.as_slice());
+    }
+    buf.extend(bloom.to_bytes());
+    buf
+}
+
+type MarkState = (BloomFilter, usize, Vec<(Vec<u8>, Hash)>, BTreeSet<Hash>);
+
+fn decode_mark(buf: &[u8]) -> Result<MarkState, TrieError> {
+    let err = || TrieError::DecodeError("invalid pruner mark state".to_string());
+    if buf.len() < 16 {
+        return Err(err());
+    }
+    let next_root = read_u64(buf) as usize;
+    let count = read_u64(&buf[8..]);
+    let mut pos = 16;
+    let mut stack = Vec::new();
+    for _ in 0..count {
+        if buf.len() < pos + 8 {
+            return Err(err());
+        }
+        let len = read_u64(&buf[pos..]) as usize;
+        pos += 8;
+        if buf.len() < pos + len + 32 {
+            return Err(err());
+        }
+        let path = buf[pos..pos + len].to_vec();
+        stack.push((path, to_hash(&buf[pos + len..pos + len + 32])?));
+        pos += len + 32;
+    }
+    if buf.len() < pos + 8 {
+        return Err(err());
+    }
+    let count = read_u64(&buf[pos..]) as usize;
+    pos += 8;
+    if (buf.len() - pos) / 32 < count {
+        return Err(err());
+    }
+    let mut visited = BTreeSet::new();
+    for _ in 0..count {
+        visited.insert(to_hash(&buf[pos..pos + 32])?);
+        pos += 32;
+    }
+    Ok((BloomFilter::from_bytes(&buf[pos..])?, next_root, stack, visited))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::NodeReader, hashdb::HashDB, kvstore::{KeyValue, MemoryStore}, Trie, ID};
+
+    // 两个版本的trie写入同一个存储, 返回(旧root, 新root, 存储)
+    fn two_versions() -> (Hash, Hash, Rc<MemoryStore>) {
+        let store = Rc::new(MemoryStore::new());
+        let db = Rc::new(HashDB::new(store.clone()));
+        let mut t = Trie::new(ID::trie_id(Hash::default()));
+        for i in 0..3000u32 {
+            t.try_update(keccak256(&i.to_be_bytes()).to_vec(), Some(vec![i as u8; 1 + (i % 40) as usize])).unwrap();
+        }
+        let (old, set) = t.commit().unwrap();
+        db.update(&set).unwrap();
+        db.reference(old).unwrap();
+        let mut t = Trie::open(ID::trie_id(old), db.clone()).unwrap();
+        for i in (0..3000u32).step_by(6) {
+            t.try_update(keccak256(&i.to_be_bytes()).to_vec(), Some(vec![0xff; 33])).unwrap();
+        }
+        let (new, set) = t.commit().unwrap();
+        db.update(&set).unwrap();
+        db.reference(new).unwrap();
+        db.flush().unwrap();
+        (old, new, store)
+    }
+
+    fn dump(store: &MemoryStore) -> Vec<KeyValue> {
+        store.scan(&[], usize::MAX).unwrap()
+    }
+
+    #[test]
+    fn prune_keeps_live_trie() {
+        let (old, new, store) = two_versions();
+        let before = store.len();
+        let progress = Pruner::new(store.clone(), vec![new], 10_000).prune(&mut |_| true).unwrap();
+        assert_eq!(progress.phase, PrunePhase::Done);
+        assert!(progress.deleted > 0);
+        assert_eq!(store.len(), before - 2 * progress.deleted as usize);
+        let t = Trie::open(ID::trie_id(new), Rc::new(HashDB::new(store.clone()))).unwrap();
+        assert_eq!(t.iter().count(), 3000);
+        assert!(Trie::open(ID::trie_id(old), Rc::new(HashDB::new(store.clone()))).is_err());
+        assert!(store.get(PRUNER_PROGRESS_KEY).unwrap().is_none());
+    }
+
+    // 每次报告进度都中断, 恢复后的累计计数和结果与不中断时一致
+    #[test]
+    fn resume_reports_totals() {
+        let (_, new, full) = two_versions();
+        let expected = Pruner::new(full.clone(), vec![new], 10_000).prune(&mut |_| true).unwrap();
+
+        let (_, new, store) = two_versions();
+        let mut runs = 0;
+        let mut saw_marking = false;
+        let progress = loop {
+            runs += 1;
+            let mut pruner = Pruner::new(store.clone(), vec![new], 10_000);
+            let progress = pruner.prune(&mut |p| {
+                saw_marking |= p.phase == PrunePhase::Marking && p.marked > 0;
+                false
+            }).unwrap();
+            if progress.phase == PrunePhase::Done {
+                break progress;
+            }
+        };
+        assert!(runs > 3 && saw_marking);
+        assert_eq!(progress, expected);
+        assert_eq!(dump(&store), dump(&full));
+    }
+
+    // 清理后引用计数只包含剩下的父节点, 去掉最后一个root的引用后存储应为空
+    #[test]
+    fn sweep_releases_children() {
+        let (_, new, store) = two_versions();
+        Pruner::new(store.clone(), vec![new], 10_000).prune(&mut |_| true).unwrap();
+        let db = HashDB::new(store.clone());
+        assert_eq!(db.refcount(new).unwrap(), Some(1));
+        db.dereference(new).unwrap();
+        assert!(store.is_empty());
+    }
+
+    // 多个root共享的节点只标记一次, 中断恢复后计数不变
+    #[test]
+    fn shared_nodes_marked_once() {
+        let (old, new, store) = two_versions();
+        let db = HashDB::new(store.clone());
+        let mut live = BTreeSet::new();
+        let mut stack = vec![old, new];
+        while let Some(hash) = stack.pop() {
+            if live.insert(hash) {
+                let blob = db.node(Hash::default(), &[], hash).unwrap().unwrap();
+                stack.extend(child_hashes(&blob).unwrap().into_iter().map(|(_, h)| h));
+            }
+        }
+        let (_, _, full) = two_versions();
+        let expected = Pruner::new(full, vec![old, new, old], 10_000).prune(&mut |_| true).unwrap();
+        assert_eq!(expected.marked, live.len() as u64);
+        assert_eq!(expected.deleted, 0);
+
+        let progress = loop {
+            let progress = Pruner::new(store.clone(), vec![old, new, old], 10_000).prune(&mut |_| false).unwrap();
+            if progress.phase == PrunePhase::Done {
+                break progress;
+            }
+        };
+        assert_eq!(progress, expected);
+    }
+
+    #[test]
+    fn mark_state_round_trip() {
+        let mut bloom = BloomFilter::new(100, 0.01);
+        bloom.insert(&keccak256(b"a"));
+        let stack = vec![(vec![1, 2, 3], keccak256(b"b")), (Vec::new(), keccak256(b"c"))];
+        let visited: BTreeSet<Hash> = [keccak256(b"d"), keccak256(b"e")].into_iter().collect();
+        let (decoded, next_root, decoded_stack, decoded_visited) = decode_mark(&encode_mark(&bloom, 7, &stack, &visited)).unwrap();
+        assert!(decoded.contains(&keccak256(b"a")));
+        assert_eq!((next_root, decoded_stack, decoded_visited), (7, stack, visited));
+        assert!(decode_mark(&[0; 10]).is_err());
+    }
+}
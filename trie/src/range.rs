@@ -0,0 +1,417 @@
+use std::rc::Rc;
+
+use crate::{common::{key_to_hex, Hash}, database::NodeReader, node::{Node, NodeType, NilNode, ShortNode}, stacktrie::StackTrie, Trie, TrieError, ID};
+
+// 子树(所有以某hex路径为前缀的key)和区间[start, end)的关系
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Overlap {
+    // 子树完全在区间外
+    Outside,
+    // 子树完全在区间内
+    Inside,
+    // 只有一部分在区间内, 需要展开子树
+    Partial,
+}
+
+// 不带终止符的hex key, 这样前缀更短的key排在前面, 与原始key的顺序一致
+pub(crate) fn hex_bound(key: &[u8]) -> Vec<u8> {
+    let mut hex = key_to_hex(key);
+    hex.pop();
+    hex
+}
+
+// path下的所有key与区间[start, end)的关系, end为None表示没有上界
+pub(crate) fn overlap(path: &[u8], start: &[u8], end: Option<&[u8]>) -> Overlap {
+    // 全部小于start
+    if path < start && !start.starts_with(path) {
+        return Overlap::Outside;
+    }
+    if let Some(end) = end {
+        // 全部不小于end
+        if path >= end {
+            return Overlap::Outside;
+        }
+        if path >= start && !end.starts_with(path) {
+            return Overlap::Inside;
+        }
+        return Overlap::Partial;
+    }
+    if path >= start {
+        return Overlap::Inside;
+    }
+    Overlap::Partial
+}
+
+impl Trie {
+    // 证明区间[start, end)内没有key: 两个边界key的证明, 包含两侧最近的key或trie的边缘
+    pub fn prove_gap(&mut self, start: &[u8], end: &[u8]) -> Result<Vec<Vec<u8>>, TrieError> {
+        let mut proof = self.prove(start)?;
+        for node in self.prove(end)? {
+            if !proof.contains(&node) {
+                proof.push(node);
+            }
+        }
+        Ok(proof)
+    }
+}
+
+// 验证区间[start, end)内没有key, 证明不足以判断时返回InvalidProof错误
+// 返回false表示证明中能找到区间内的key
+pub fn verify_gap(root: Hash, start: &[u8], end: &[u8], proof: &[Vec<u8>]) -> Result<bool, TrieError> {
+    if start >= end {
+        return Ok(true);
+    }
+    let (start, end) = (hex_bound(start), hex_bound(end));
+    let ret = Trie::from_witness(root, proof).and_then(|t| has_key_in(&t, Rc::clone(&t.root), Vec::new(), &start, Some(&end)));
+    Ok(!proof_result(ret)?)
+}
+
+// 证明中缺少节点说明证明不完整
+fn proof_result<T>(ret: Result<T, TrieError>) -> Result<T, TrieError> {
+    match ret {
+        Err(TrieError::MissingNode { path, hash }) => Err(TrieError::InvalidProof(format!("missing node {} at path {}", hash, hex::encode(path)))),
+        ret => ret,
+    }
+}
+
+// 子树中是否有key落在区间内, 只展开和区间边界相交的子树
+fn has_key_in(t: &Trie, n: Rc<dyn Node>, path: Vec<u8>, start: &[u8], end: Option<&[u8]>) -> Result<bool, TrieError> {
+    match overlap(&path, start, end) {
+        Overlap::Outside => return Ok(false),
+        // 非空子树至少有一个key
+        Overlap::Inside => return Ok(n.kind() != NodeType::NullNode),
+        Overlap::Partial => {},
+    }
+    match n.kind() {
+        NodeType::NullNode => Ok(false),
+        // path等于key且部分相交, 说明key就是start
+        NodeType::ValueNode => Ok(path.as_slice() >= start && end.is_none_or(|end| path.as_slice() < end)),
+        NodeType::HashNode => {
+            let hn = n.into_hash_node()?;
+            let rn = t.resolver.resolve(Hash::from(hn.0), &path)?;
+            has_key_in(t, rn, path, start, end)
+        },
+        NodeType::ShortNode => {
+            let sn = n.into_short_node()?;
+            let mut child_path = path;
+            child_path.extend(sn.key.iter().filter(|v| **v < 16));
+            has_key_in(t, sn.val, child_path, start, end)
+        },
+        NodeType::FullNode => {
+            let f_n = n.into_full_node()?;
+            if let Some(value) = &f_n.children[16] {
+                if has_key_in(t, Rc::clone(value), path.clone(), start, end)? {
+                    return Ok(true);
+                }
+            }
+            for (i, child) in f_n.children.iter().take(16).enumerate() {
+                if let Some(child) = child {
+                    let mut child_path = path.clone();
+                    child_path.push(i as u8);
+                    if has_key_in(t, Rc::clone(child), child_path, start, end)? {
+                        return Ok(true);
+                    }
+                }
+            }
+            Ok(false)
+        },
+    }
+}
+
+// snap式区间请求的应答: 从origin开始按顺序的键值对, 以及origin和最后一个key的证明
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RangeResponse {
+    pub keys: Vec<Vec<u8>>,
+    pub values: Vec<Vec<u8>>,
+    pub proof: Vec<Vec<u8>>,
+}
+
+// 返回root对应trie中从origin开始的键值对, 超过limit的第一个key也会返回以证明区间结束
+// 键值对的总字节数达到max_bytes后停止, 至少返回一个
+pub fn serve_range(reader: Rc<dyn NodeReader>, root: Hash, origin: Hash, limit: Hash, max_bytes: usize) -> Result<RangeResponse, TrieError> {
+    let mut t = Trie::open(ID::trie_id(root), reader)?;
+    let mut resp = RangeResponse::default();
+    let mut size = 0;
+    for item in t.iter_from(origin.as_slice()) {
+        let (key, value) = item?;
+        size += key.len() + value.len();
+        let beyond = key.as_slice() > limit.as_slice();
+        resp.keys.push(key);
+        resp.values.push(value);
+        if beyond || size >= max_bytes {
+            break;
+        }
+    }
+    resp.proof = match resp.keys.last() {
+        Some(last) => {
+            let last = last.clone();
+            t.prove_gap(origin.as_slice(), &last)?
+        },
+        None => t.prove(origin.as_slice())?,
+    };
+    Ok(resp)
+}
+
+// 验证区间应答, 返回last之后是否还有key
+// 没有证明时应答必须从头开始包含整个trie
+pub fn verify_range(root: Hash, origin: Hash, resp: &RangeResponse) -> Result<bool, TrieError> {
+    if resp.keys.len() != resp.values.len() {
+        return Err(TrieError::InvalidProof("key and value counts differ".to_string()));
+    }
+    for (i, key) in resp.keys.iter().enumerate() {
+        if (i == 0 && key.as_slice() < origin.as_slice()) || (i > 0 && key <= &resp.keys[i - 1]) {
+            return Err(TrieError::InvalidProof("keys are not in increasing order from origin".to_string()));
+        }
+        if resp.values[i].is_empty() {
+            return Err(TrieError::InvalidProof("empty value in range".to_string()));
+        }
+    }
+    if resp.proof.is_empty() {
+        if !origin.iter().all(|b| *b == 0) {
+            return Err(TrieError::InvalidProof("range without proof must start at the beginning".to_string()));
+        }
+        let mut t = Trie::new(ID::trie_id(root));
+        for (key, value) in resp.keys.iter().zip(resp.values.iter()) {
+            t.try_update(key.clone(), Some(value.clone()))?;
+        }
+        if t.hash()? != root {
+            return Err(TrieError::InvalidProof("range does not match root".to_string()));
+        }
+        return Ok(false);
+    }
+    let mut t = proof_result(Trie::from_witness(root, &resp.proof))?;
+    let start = hex_bound(origin.as_slice());
+    let last = match resp.keys.last() {
+        Some(last) => last,
+        None => {
+            // 没有key时origin之后必须没有key
+            if proof_result(has_key_in(&t, Rc::clone(&t.root), Vec::new(), &start, None))? {
+                return Err(TrieError::InvalidProof("range is empty but keys exist after origin".to_string()));
+            }
+            return Ok(false);
+        },
+    };
+    // 不大于last的最小后继: last后接0x00
+    let mut end = hex_bound(last);
+    end.extend([0, 0]);
+    // 去掉区间内的所有节点, 再插入应答中的键值对, 得到的root必须一致
+    let pruned = proof_result(prune(&t, Rc::clone(&t.root), Vec::new(), &start, &end))?;
+    t.root = pruned.unwrap_or_else(|| Rc::new(NilNode));
+    for (key, value) in resp.keys.iter().zip(resp.values.iter()) {
+        proof_result(t.try_update(key.clone(), Some(value.clone())))?;
+    }
+    if t.hash()? != root {
+        return Err(TrieError::InvalidProof("range does not match root".to_string()));
+    }
+    proof_result(has_key_in(&t, Rc::clone(&t.root), Vec::new(), &end, None))
+}
+
+// 验证区间应答并把键值对按顺序写入builder, 返回是否还有更多key
+pub fn consume_range(root: Hash, origin: Hash, resp: &RangeResponse, builder: &mut StackTrie) -> Result<bool, TrieError> {
+    let more = verify_range(root, origin, resp)?;
+    for (key, value) in resp.keys.iter().zip(resp.values.iter()) {
+        builder.update(key, value.clone())?;
+    }
+    Ok(more)
+}
+
+// 删除子树中落在区间内的key, 子树全部删除时返回None
+fn prune(t: &Trie, n: Rc<dyn Node>, path: Vec<u8>, start: &[u8], end: &[u8]) -> Result<Option<Rc<dyn Node>>, TrieError> {
+    match overlap(&path, start, Some(end)) {
+        Overlap::Outside => return Ok(Some(n)),
+        Overlap::Inside => return Ok(None),
+        Overlap::Partial => {},
+    }
+    match n.kind() {
+        NodeType::NullNode => Ok(None),
+        NodeType::ValueNode => {
+            if path.as_slice() >= start && path.as_slice() < end {
+                return Ok(None);
+            }
+            Ok(Some(n))
+        },
+        NodeType::HashNode => {
+            let hn = n.into_hash_node()?;
+            let rn = t.resolver.resolve(Hash::from(hn.0), &path)?;
+            prune(t, rn, path, start, end)
+        },
+        NodeType::ShortNode => {
+            let sn = n.into_short_node()?;
+            let mut child_path = path;
+            child_path.extend(sn.key.iter().filter(|v| **v < 16));
+            match prune(t, Rc::clone(&sn.val), child_path, start, end)? {
+                Some(child) => Ok(Some(Rc::new(ShortNode::new(sn.key, child, t.new_flag())))),
+                None => Ok(None),
+            }
+        },
+        NodeType::FullNode => {
+            let mut f_n = n.into_full_node()?;
+            f_n.flags = t.new_flag();
+            let mut remain = 0;
+            for i in 0..17 {
+                if let Some(child) = f_n.children[i].clone() {
+                    let mut child_path = path.clone();
+                    if i < 16 {
+                        child_path.push(i as u8);
+                    }
+                    f_n.children[i] = prune(t, child, child_path, start, end)?;
+                    if f_n.children[i].is_some() {
+                        remain += 1;
+                    }
+                }
+            }
+            if remain == 0 {
+                return Ok(None);
+            }
+            Ok(Some(Rc::new(f_n)))
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{common::{keccak256, to_hash}, database::MemoryDB};
+
+    fn sample() -> (Trie, Vec<Vec<u8>>) {
+        let mut t = Trie::new(ID::trie_id(Hash::default()));
+        let mut keys = Vec::new();
+        for i in 0..300u32 {
+            let key = keccak256(&i.to_be_bytes()).to_vec();
+            t.try_update(key.clone(), Some(vec![i as u8; 1 + (i % 40) as usize])).unwrap();
+            keys.push(key);
+        }
+        keys.sort();
+        (t, keys)
+    }
+
+    fn after(key: &[u8]) -> Vec<u8> {
+        let mut k = key.to_vec();
+        k.push(0);
+        k
+    }
+
+    fn gap(t: &mut Trie, start: &[u8], end: &[u8]) -> Result<bool, TrieError> {
+        let root = t.hash()?;
+        let proof = t.prove_gap(start, end)?;
+        verify_gap(root, start, end, &proof)
+    }
+
+    // 相邻key之间的区间为空, 包含key的区间不为空
+    #[test]
+    fn gaps_between_keys() {
+        let (mut t, keys) = sample();
+        for w in keys.windows(2).step_by(7) {
+            assert!(gap(&mut t, &after(&w[0]), &w[1]).unwrap());
+            assert!(!gap(&mut t, &w[0], &w[1]).unwrap());
+            assert!(!gap(&mut t, &after(&w[0]), &after(&w[1])).unwrap());
+        }
+        assert!(!gap(&mut t, &keys[10], &keys[20]).unwrap());
+        // trie的两端
+        assert!(gap(&mut t, &[], &keys[0]).unwrap());
+        assert!(gap(&mut t, &after(&keys[keys.len() - 1]), &[0xff; 33]).unwrap());
+        assert!(!gap(&mut t, &[], &after(&keys[0])).unwrap());
+        assert!(gap(&mut t, &keys[5], &keys[5]).unwrap());
+    }
+
+    // 只有一侧边界的证明无法判断, 返回InvalidProof
+    #[test]
+    fn incomplete_gap_proof() {
+        let (mut t, keys) = sample();
+        let root = t.hash().unwrap();
+        // 两个边界key在root下的不同分支中
+        let w = keys.windows(2).find(|w| w[0][0] >> 4 != w[1][0] >> 4).unwrap();
+        let (start, end) = (after(&w[0]), w[1].clone());
+        assert!(verify_gap(root, &start, &end, &t.prove_gap(&start, &end).unwrap()).unwrap());
+        let proof = t.prove(&start).unwrap();
+        assert!(matches!(verify_gap(root, &start, &end, &proof), Err(TrieError::InvalidProof(_))));
+        let proof = t.prove_gap(&after(&keys[3]), &keys[4]).unwrap();
+        assert!(verify_gap(keccak256(b"x"), &after(&keys[3]), &keys[4], &proof).is_err());
+        assert!(verify_gap(Hash::empty_root_hash(), &[], &[0xff], &[]).unwrap());
+    }
+
+    fn stored() -> (Hash, Rc<MemoryDB>) {
+        let (mut t, _) = sample();
+        let (root, set) = t.commit().unwrap();
+        let db = Rc::new(MemoryDB::new());
+        db.update(&set);
+        (root, db)
+    }
+
+    // 下一个hash, 全为0xff时返回None
+    fn next(h: Hash) -> Option<Hash> {
+        let mut b = *h;
+        for i in (0..32).rev() {
+            if b[i] < 0xff {
+                b[i] += 1;
+                return Some(Hash::from(b));
+            }
+            b[i] = 0;
+        }
+        None
+    }
+
+    // 按字节预算分批请求区间, 验证后写入StackTrie, 最终得到完整的trie
+    #[test]
+    fn range_sync() {
+        let (root, db) = stored();
+        let mut builder = StackTrie::new(Hash::default());
+        let mut origin = Hash::default();
+        let mut rounds = 0;
+        loop {
+            let resp = serve_range(db.clone(), root, origin, Hash::from([0xff; 32]), 1000).unwrap();
+            let more = consume_range(root, origin, &resp, &mut builder).unwrap();
+            rounds += 1;
+            if !more {
+                break;
+            }
+            origin = next(to_hash(resp.keys.last().unwrap()).unwrap()).unwrap();
+        }
+        assert!(rounds > 5);
+        let (synced, set) = builder.commit().unwrap();
+        assert_eq!(synced, root);
+        assert_eq!(set.len(), db.len());
+    }
+
+    // 超过limit的第一个key也返回, 客户端据此知道区间已结束
+    #[test]
+    fn range_limit() {
+        let (root, db) = stored();
+        let (_, keys) = sample();
+        let limit = to_hash(&keys[50]).unwrap();
+        let resp = serve_range(db.clone(), root, Hash::default(), limit, usize::MAX).unwrap();
+        assert_eq!(resp.keys, keys[..52].to_vec());
+        assert!(verify_range(root, Hash::default(), &resp).unwrap());
+
+        let last = next(to_hash(&keys[keys.len() - 1]).unwrap()).unwrap();
+        let resp = serve_range(db.clone(), root, last, Hash::from([0xff; 32]), usize::MAX).unwrap();
+        assert!(resp.keys.is_empty() && !resp.proof.is_empty());
+        assert!(!verify_range(root, last, &resp).unwrap());
+    }
+
+    #[test]
+    fn range_rejects_tampering() {
+        let (root, db) = stored();
+        let origin = Hash::from([0x40; 32]);
+        let resp = serve_range(db, root, origin, Hash::from([0xff; 32]), 500).unwrap();
+        assert!(resp.keys.len() > 3);
+        assert!(verify_range(root, origin, &resp).unwrap());
+
+        let mut r = resp.clone();
+        r.keys.remove(1);
+        r.values.remove(1);
+        assert!(matches!(verify_range(root, origin, &r), Err(TrieError::InvalidProof(_))));
+        let mut r = resp.clone();
+        r.values[2] = vec![0xee];
+        assert!(verify_range(root, origin, &r).is_err());
+        let mut r = resp.clone();
+        r.keys.swap(0, 1);
+        assert!(verify_range(root, origin, &r).is_err());
+        // 没有证明时必须从头开始
+        let mut r = resp.clone();
+        r.proof.clear();
+        assert!(verify_range(root, origin, &r).is_err());
+        let mut builder = StackTrie::new(Hash::default());
+        assert!(consume_range(keccak256(b"x"), origin, &resp, &mut builder).is_err());
+    }
+}
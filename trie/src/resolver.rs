@@ -0,0 +1,151 @@
+use std::{rc::Rc, cell::RefCell, collections::BTreeSet};
+
+use crate::{cache::{NodeCache, DEFAULT_CACHE_SIZE}, common::Hash, database::NodeReader, hasher::Hasher, node::{decode_node, HashNode, Node, NodeType}, witness::Witness, TrieError};
+
+// 把HashNode解析成实际节点, 解析过的节点缓存起来供后续读取
+#[derive(Clone)]
+pub(crate) struct Resolver {
+    owner: Hash,
+    reader: Option<Rc<dyn NodeReader>>,
+    cache: Rc<RefCell<NodeCache>>,
+    // 从存储加载过的节点路径, 提交时据此判断哪些路径上的节点被删除
+    loaded: Rc<RefCell<BTreeSet<Vec<u8>>>>,
+    // 开启记录后, 解析或访问过的存储节点
+    witness: Rc<RefCell<Option<Witness>>>,
+}
+
+impl Resolver {
+    pub(crate) fn new(owner: Hash, reader: Option<Rc<dyn NodeReader>>) -> Self {
+        Resolver::with_cache(owner, reader, Rc::new(RefCell::new(NodeCache::new(DEFAULT_CACHE_SIZE))))
+    }
+    pub(crate) fn with_cache(owner: Hash, reader: Option<Rc<dyn NodeReader>>, cache: Rc<RefCell<NodeCache>>) -> Self {
+        Resolver { owner, reader, cache, loaded: Rc::new(RefCell::new(BTreeSet::new())), witness: Rc::new(RefCell::new(None)) }
+    }
+    // 复制一个独立记录加载路径的resolver, 缓存和存储共享
+    pub(crate) fn fork(&self) -> Self {
+        let loaded = self.loaded.borrow().clone();
+        let witness = self.witness.borrow().clone();
+        Resolver { owner: self.owner, reader: self.reader.clone(), cache: Rc::clone(&self.cache), loaded: Rc::new(RefCell::new(loaded)), witness: Rc::new(RefCell::new(witness)) }
+    }
+    pub(crate) fn loaded_paths(&self) -> BTreeSet<Vec<u8>> {
+        self.loaded.borrow().clone()
+    }
+    pub(crate) fn set_loaded_paths(&self, paths: BTreeSet<Vec<u8>>) {
+        *self.loaded.borrow_mut() = paths;
+    }
+    pub(crate) fn has_reader(&self) -> bool {
+        self.reader.is_some()
+    }
+    pub(crate) fn cache(&self) -> Rc<RefCell<NodeCache>> {
+        Rc::clone(&self.cache)
+    }
+
+    pub(crate) fn start_witness(&self) {
+        *self.witness.borrow_mut() = Some(Witness::new());
+    }
+    pub(crate) fn stop_witness(&self) -> Option<Witness> {
+        self.witness.borrow_mut().take()
+    }
+    pub(crate) fn witness(&self) -> Option<Witness> {
+        self.witness.borrow().clone()
+    }
+    // 记录访问到的存储节点, 只有未修改且有hash的节点才是存储中的节点
+    pub(crate) fn touch(&self, n: &Rc<dyn Node>) -> Result<(), TrieError> {
+        let mut witness = self.witness.borrow_mut();
+        let witness = match witness.as_mut() {
+            Some(w) => w,
+            None => return Ok(()),
+        };
+        if n.kind() != NodeType::ShortNode && n.kind() != NodeType::FullNode {
+            return Ok(());
+        }
+        let hash = match n.cache() {
+            (Some(hash), false) => Hash::from(hash.0),
+            _ => return Ok(()),
+        };
+        if !witness.contains(&hash) {
+            let blob = Hasher::new(false).encode_node(Rc::clone(n))?;
+            witness.add_with_hash(hash, blob);
+        }
+        Ok(())
+    }
+
+    // 记录解析到的存储节点: 加入加载路径并写入witness, 从存储读到的数据直接使用, 缓存命中的节点重新编码
+    pub(crate) fn record(&self, hash: Hash, path: &[u8], n: &Rc<dyn Node>, blob: Option<Vec<u8>>) -> Result<(), TrieError> {
+        self.loaded.borrow_mut().insert(path.to_vec());
+        match blob {
+            Some(blob) => {
+                if let Some(witness) = self.witness.borrow_mut().as_mut() {
+                    witness.add_with_hash(hash, blob);
+                }
+                Ok(())
+            },
+            None => self.touch(n),
+        }
+    }
+
+    pub(crate) fn resolve(&self, hash: Hash, path: &[u8]) -> Result<Rc<dyn Node>, TrieError> {
+        let cached = self.cache.borrow_mut().get(&hash);
+        if let Some(n) = cached {
+            self.record(hash, path, &n, None)?;
+            return Ok(n);
+        }
+        let reader = match &self.reader {
+            Some(reader) => reader,
+            None => return Err(TrieError::missing(path, hash)),
+        };
+        match reader.node(self.owner, path, hash)? {
+            Some(blob) => {
+                let n = decode_node(Some(HashNode::from(*hash)), &blob)?;
+                self.cache.borrow_mut().insert(hash, Rc::clone(&n), blob.len());
+                self.record(hash, path, &n, Some(blob))?;
+                Ok(n)
+            },
+            None => Err(TrieError::missing(path, hash)),
+        }
+    }
+
+    // 批量解析, reqs为(hex路径, hash), 未缓存的节点合并成一次读取
+    pub(crate) fn resolve_many(&self, reqs: &[(Vec<u8>, Hash)]) -> Result<Vec<Rc<dyn Node>>, TrieError> {
+        let mut ret: Vec<Option<Rc<dyn Node>>> = Vec::with_capacity(reqs.len());
+        let mut fetch = Vec::new();
+        {
+            let mut cache = self.cache.borrow_mut();
+            for (i, (_, hash)) in reqs.iter().enumerate() {
+                let cached = cache.get(hash);
+                if cached.is_none() {
+                    fetch.push(i);
+                }
+                ret.push(cached);
+            }
+        }
+        if !fetch.is_empty() {
+            let reader = match &self.reader {
+                Some(reader) => reader,
+                None => {
+                    let (path, hash) = &reqs[fetch[0]];
+                    return Err(TrieError::missing(path, *hash));
+                },
+            };
+            let batch: Vec<(Vec<u8>, Hash)> = fetch.iter().map(|i| reqs[*i].clone()).collect();
+            let blobs = reader.nodes(self.owner, &batch)?;
+            let mut cache = self.cache.borrow_mut();
+            for (i, blob) in fetch.into_iter().zip(blobs) {
+                let (path, hash) = &reqs[i];
+                let blob = match blob {
+                    Some(blob) => blob,
+                    None => return Err(TrieError::missing(path, *hash)),
+                };
+                let n = decode_node(Some(HashNode::from(**hash)), &blob)?;
+                cache.insert(*hash, Rc::clone(&n), blob.len());
+                ret[i] = Some(n);
+            }
+        }
+        self.loaded.borrow_mut().extend(reqs.iter().map(|(path, _)| path.clone()));
+        let ret: Vec<Rc<dyn Node>> = ret.into_iter().flatten().collect();
+        for n in ret.iter() {
+            self.touch(n)?;
+        }
+        Ok(ret)
+    }
+}
@@ -0,0 +1,73 @@
+use crate::TrieError;
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Kind {
+    String,
+    List,
+}
+
+// 拆出第一个RLP值, 返回(类型, 内容, 剩余数据)
+pub(crate) fn split(buf: &[u8]) -> Result<(Kind, &[u8], &[u8]), TrieError> {
+    if buf.is_empty() {
+        return Err(TrieError::DecodeError("unexpected end of input".to_string()));
+    }
+    let b = buf[0];
+    let (kind, offset, size) = match b {
+        0..=0x7f => (Kind::String, 0, 1),
+        0x80..=0xb7 => (Kind::String, 1, (b - 0x80) as usize),
+        0xb8..=0xbf => {
+            let n = (b - 0xb7) as usize;
+            (Kind::String, 1 + n, read_size(&buf[1..], n)?)
+        },
+        0xc0..=0xf7 => (Kind::List, 1, (b - 0xc0) as usize),
+        0xf8..=0xff => {
+            let n = (b - 0xf7) as usize;
+            (Kind::List, 1 + n, read_size(&buf[1..], n)?)
+        },
+    };
+    if buf.len() - offset < size {
+        return Err(TrieError::DecodeError("value size exceeds available input".to_string()));
+    }
+    Ok((kind, &buf[offset..offset+size], &buf[offset+size..]))
+}
+
+pub(crate) fn split_list(buf: &[u8]) -> Result<(&[u8], &[u8]), TrieError> {
+    let (kind, content, rest) = split(buf)?;
+    if kind != Kind::List {
+        return Err(TrieError::DecodeError("expected list".to_string()));
+    }
+    Ok((content, rest))
+}
+
+pub(crate) fn split_string(buf: &[u8]) -> Result<(&[u8], &[u8]), TrieError> {
+    let (kind, content, rest) = split(buf)?;
+    if kind != Kind::String {
+        return Err(TrieError::DecodeError("expected string".to_string()));
+    }
+    Ok((content, rest))
+}
+
+// 列表内容中的值数量
+pub(crate) fn count_values(mut buf: &[u8]) -> Result<usize, TrieError> {
+    let mut i = 0;
+    while !buf.is_empty() {
+        let (_, _, rest) = split(buf)?;
+        buf = rest;
+        i += 1;
+    }
+    Ok(i)
+}
+
+fn read_size(buf: &[u8], n: usize) -> Result<usize, TrieError> {
+    if n > buf.len() || n > 8 {
+        return Err(TrieError::DecodeError("invalid size prefix".to_string()));
+    }
+    if buf[0] == 0 {
+        return Err(TrieError::DecodeError("non-canonical size prefix".to_string()));
+    }
+    let mut size = 0_usize;
+    for v in &buf[..n] {
+        size = size << 8 | *v as usize;
+    }
+    Ok(size)
+}
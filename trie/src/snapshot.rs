@@ -0,0 +1,128 @@
+use std::{rc::Rc, cell::RefCell};
+
+use crate::{common::{Hash, key_to_hex}, hasher::Hasher, iterator::TrieIterator, node::{Node, NodeType}, resolver::Resolver, get_node, TrieError};
+
+// trie的只读快照, 与原trie共享节点, 原trie后续的修改不影响快照
+pub struct TrieSnapshot {
+    // 计算hash后替换为带hash缓存的root, 内容不变
+    root: RefCell<Rc<dyn Node>>,
+    owner: Hash,
+    resolver: Resolver,
+}
+
+impl TrieSnapshot {
+    pub(crate) fn new(root: Rc<dyn Node>, owner: Hash, resolver: Resolver) -> Self {
+        TrieSnapshot { root: RefCell::new(root), owner, resolver }
+    }
+    pub fn owner(&self) -> Hash {
+        self.owner
+    }
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, TrieError> {
+        let root = Rc::clone(&self.root.borrow());
+        let ret = get_node(root, key_to_hex(key).as_slice(), 0, &self.resolver)?;
+        Ok(ret.value)
+    }
+    pub fn iter(&self) -> TrieIterator {
+        TrieIterator::new(Rc::clone(&self.root.borrow()), self.resolver.clone())
+    }
+    pub fn hash(&self) -> Result<Hash, TrieError> {
+        let root = Rc::clone(&self.root.borrow());
+        if root.kind() == NodeType::NullNode {
+            return Ok(Hash::empty_root_hash());
+        }
+        let mut h = Hasher::new(false);
+        let (hashed, cached) = h.hash_node(root, true);
+        *self.root.borrow_mut() = cached;
+        let hn = hashed.into_hash_node()?;
+        Ok(Hash::from(hn.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use crate::{common::{keccak256, Hash}, database::MemoryDB, Trie, ID};
+
+    fn key(i: u32) -> Vec<u8> {
+        keccak256(&i.to_be_bytes()).to_vec()
+    }
+
+    fn filled(n: u32) -> Trie {
+        let mut t = Trie::new(ID::trie_id(Hash::default()));
+        for i in 0..n {
+            t.try_update(key(i), Some(vec![i as u8; 1 + (i % 40) as usize])).unwrap();
+        }
+        t
+    }
+
+    // 原trie继续修改, 快照的读取、遍历和hash不变
+    #[test]
+    fn snapshot_is_isolated() {
+        let mut t = filled(200);
+        let root = t.hash().unwrap();
+        let snap = t.snapshot();
+        for i in 0..100 {
+            t.try_update(key(i), None).unwrap();
+        }
+        t.try_update(key(500), Some(vec![5])).unwrap();
+        assert_ne!(t.hash().unwrap(), root);
+
+        assert_eq!(snap.hash().unwrap(), root);
+        assert_eq!(snap.get(&key(3)).unwrap(), Some(vec![3; 4]));
+        assert_eq!(snap.get(&key(500)).unwrap(), None);
+        assert_eq!(snap.iter().count(), 200);
+        assert_eq!(t.get(&key(3)).unwrap(), None);
+    }
+
+    // 未计算hash的快照也能得到与原trie一致的hash
+    #[test]
+    fn snapshot_of_dirty_trie() {
+        let mut t = filled(50);
+        let snap = t.snapshot();
+        let expected = t.hash().unwrap();
+        t.try_update(key(1), None).unwrap();
+        assert_eq!(snap.hash().unwrap(), expected);
+        assert_eq!(snap.hash().unwrap(), expected);
+    }
+
+    #[test]
+    fn copy_is_independent() {
+        let mut t = filled(100);
+        let (root, set) = t.commit().unwrap();
+        let db = Rc::new(MemoryDB::new());
+        db.update(&set);
+        let mut a = Trie::open(ID::trie_id(root), db).unwrap();
+        let mut b = a.copy();
+        a.try_update(key(1), None).unwrap();
+        b.try_update(key(1), Some(vec![9])).unwrap();
+        assert_eq!(a.get(&key(1)).unwrap(), None);
+        assert_eq!(b.get(&key(1)).unwrap(), Some(vec![9]));
+        assert_ne!(a.hash().unwrap(), b.hash().unwrap());
+        // 两者提交的节点集合分别记录删除的路径
+        let (_, sa) = a.commit().unwrap();
+        let (_, sb) = b.commit().unwrap();
+        assert!(!sa.is_empty() && !sb.is_empty());
+        b.try_update(key(1), Some(vec![1; 2])).unwrap();
+        assert_eq!(b.hash().unwrap(), root);
+    }
+
+    // 快照的读取不记录到原trie的witness中
+    #[test]
+    fn snapshot_reads_not_witnessed() {
+        let mut t = filled(100);
+        let (root, set) = t.commit().unwrap();
+        let db = Rc::new(MemoryDB::new());
+        db.update(&set);
+        let mut t = Trie::open(ID::trie_id(root), db).unwrap();
+        t.start_witness();
+        let snap = t.snapshot();
+        assert_eq!(snap.get(&key(3)).unwrap(), Some(vec![3; 4]));
+        assert_eq!(snap.iter().count(), 100);
+        assert!(t.stop_witness().unwrap().is_empty());
+
+        t.start_witness();
+        t.get(&key(3)).unwrap();
+        assert!(!t.stop_witness().unwrap().is_empty());
+    }
+}
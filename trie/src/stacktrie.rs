@@ -0,0 +1,137 @@
+use std::rc::Rc;
+
+use crate::{committer::Committer, common::{key_to_hex, Hash}, hasher::Hasher, node::{Node, NodeType}, nodeset::NodeSet, Trie, TrieError, ID};
+
+// 按key升序插入构建trie, 插入新key后, 它左侧的子树不会再变化, 立即计算hash并输出节点
+// 内存中只保留最右侧一条路径上的节点
+pub struct StackTrie {
+    trie: Trie,
+    last: Option<Vec<u8>>,
+    // 已完成的节点, 可通过take_nodes分批取出写入存储
+    set: NodeSet,
+}
+
+impl StackTrie {
+    pub fn new(owner: Hash) -> Self {
+        StackTrie { trie: Trie::new(ID::storage_trie_id(Hash::default(), owner, Hash::empty_root_hash())), last: None, set: NodeSet::new(owner) }
+    }
+
+    // key必须严格递增, value不能为空
+    pub fn update(&mut self, key: &[u8], value: Vec<u8>) -> Result<(), TrieError> {
+        if value.is_empty() {
+            return Err(TrieError::invalid("stack trie values must not be empty"));
+        }
+        if let Some(last) = &self.last {
+            if key <= last.as_slice() {
+                return Err(TrieError::invalid("stack trie keys must be inserted in increasing order"));
+            }
+        }
+        self.trie.try_update(key.to_vec(), Some(value))?;
+        let hex = key_to_hex(key);
+        self.trie.root = self.seal(Rc::clone(&self.trie.root), &hex, 0, Vec::new())?;
+        self.last = Some(key.to_vec());
+        Ok(())
+    }
+
+    // 把key路径上位于左侧的子树计算hash并输出, 替换为HashNode
+    fn seal(&mut self, n: Rc<dyn Node>, key: &[u8], pos: usize, path: Vec<u8>) -> Result<Rc<dyn Node>, TrieError> {
+        match n.kind() {
+            NodeType::ShortNode => {
+                let mut sn = n.into_short_node()?;
+                if !key[pos..].starts_with(&sn.key) {
+                    return Ok(n);
+                }
+                let mut child_path = path;
+                child_path.extend(&sn.key);
+                sn.val = self.seal(sn.val, key, pos + sn.key.len(), child_path)?;
+                Ok(Rc::new(sn))
+            },
+            NodeType::FullNode => {
+                let mut f_n = n.into_full_node()?;
+                let nibble = key[pos] as usize;
+                for i in 0..nibble.min(16) {
+                    let child = match &f_n.children[i] {
+                        Some(child) if child.kind() == NodeType::ShortNode || child.kind() == NodeType::FullNode => Rc::clone(child),
+                        _ => continue,
+                    };
+                    let (hashed, cached) = Hasher::new(false).hash_node(child, false);
+                    let mut child_path = path.clone();
+                    child_path.push(i as u8);
+                    let committed = Committer::new(&mut self.set).commit(cached, child_path)?;
+                    // 内嵌节点随父节点一起输出
+                    f_n.children[i] = Some(if hashed.kind() == NodeType::HashNode { hashed } else { committed });
+                }
+                if let Some(child) = f_n.children[nibble].clone() {
+                    let mut child_path = path;
+                    child_path.push(nibble as u8);
+                    f_n.children[nibble] = Some(self.seal(child, key, pos + 1, child_path)?);
+                }
+                Ok(Rc::new(f_n))
+            },
+            _ => Ok(n),
+        }
+    }
+
+    pub fn hash(&mut self) -> Result<Hash, TrieError> {
+        self.trie.hash()
+    }
+    // 取出目前已完成的节点
+    pub fn take_nodes(&mut self) -> NodeSet {
+        std::mem::replace(&mut self.set, NodeSet::new(self.trie.owner))
+    }
+    // 输出剩余的所有节点, 返回root
+    pub fn commit(&mut self) -> Result<(Hash, NodeSet), TrieError> {
+        let (root, rest) = self.trie.commit()?;
+        let mut set = self.take_nodes();
+        for (path, node) in rest.nodes {
+            set.add_node(path, node);
+        }
+        Ok((root, set))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{common::keccak256, database::MemoryDB, integrity::verify_integrity};
+
+    // 与普通trie得到相同的root和节点, 节点可以分批取出
+    #[test]
+    fn matches_trie() {
+        let mut items: Vec<(Vec<u8>, Vec<u8>)> = (0..1000u32).map(|i| (keccak256(&i.to_be_bytes()).to_vec(), vec![i as u8; 1 + (i % 40) as usize])).collect();
+        items.sort();
+        let mut t = Trie::new(ID::trie_id(Hash::default()));
+        let mut st = StackTrie::new(Hash::default());
+        let db = Rc::new(MemoryDB::new());
+        for (i, (k, v)) in items.iter().enumerate() {
+            t.try_update(k.clone(), Some(v.clone())).unwrap();
+            st.update(k, v.clone()).unwrap();
+            if i % 100 == 0 {
+                db.update(&st.take_nodes());
+            }
+        }
+        let (expected, full) = t.commit().unwrap();
+        let (root, rest) = st.commit().unwrap();
+        db.update(&rest);
+        assert_eq!(root, expected);
+        assert_eq!(db.len(), full.len());
+        let report = verify_integrity(db.as_ref(), Hash::default(), root).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.leaves, 1000);
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        let mut st = StackTrie::new(Hash::default());
+        st.update(&[2], vec![1]).unwrap();
+        assert!(matches!(st.update(&[2], vec![1]), Err(TrieError::InvalidNode(_))));
+        assert!(matches!(st.update(&[1], vec![1]), Err(TrieError::InvalidNode(_))));
+        assert!(matches!(st.update(&[3], vec![]), Err(TrieError::InvalidNode(_))));
+        // 前缀更短的key排在前面
+        st.update(&[2, 0], vec![2]).unwrap();
+        let mut t = Trie::new(ID::trie_id(Hash::default()));
+        t.try_update(vec![2], Some(vec![1])).unwrap();
+        t.try_update(vec![2, 0], Some(vec![2])).unwrap();
+        assert_eq!(st.hash().unwrap(), t.hash().unwrap());
+    }
+}
@@ -0,0 +1,423 @@
+use std::{collections::BTreeMap, rc::Rc};
+
+use crate::{common::{keccak256, to_hash, Hash}, database::NodeReader, json::{from_hex, to_hex, to_quantity, Json}, nodeset::NodeSet, proof::verify_proof, rlp, writer::EncodeBuffer, Trie, TrieError, ID};
+
+pub type Address = [u8; 20];
+
+// 没有代码的账户的codeHash, 即keccak256("")
+pub fn empty_code_hash() -> Hash {
+    keccak256(&[])
+}
+
+// 256位无符号整数, 大端
+pub type U256 = [u8; 32];
+
+// 账户trie中保存的账户数据, RLP编码为[nonce, balance, storageRoot, codeHash]
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateAccount {
+    pub nonce: u64,
+    pub balance: U256,
+    pub storage_root: Hash,
+    pub code_hash: Hash,
+}
+
+impl Default for StateAccount {
+    fn default() -> Self {
+        StateAccount { nonce: 0, balance: [0; 32], storage_root: Hash::empty_root_hash(), code_hash: empty_code_hash() }
+    }
+}
+
+impl StateAccount {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = EncodeBuffer::new();
+        let offset = w.list();
+        w.write_bytes(trim(&self.nonce.to_be_bytes()));
+        w.write_bytes(trim(&self.balance));
+        w.write_bytes(self.storage_root.as_slice());
+        w.write_bytes(self.code_hash.as_slice());
+        w.list_end(offset);
+        w.encode_bytes()
+    }
+    pub fn decode(buf: &[u8]) -> Result<Self, TrieError> {
+        let (elems, _) = rlp::split_list(buf)?;
+        let (nonce, rest) = rlp::split_string(elems)?;
+        let (balance, rest) = rlp::split_string(rest)?;
+        let (storage_root, rest) = rlp::split_string(rest)?;
+        let (code_hash, _) = rlp::split_string(rest)?;
+        Ok(StateAccount { nonce: be_u64(nonce)?, balance: be_u256(balance)?, storage_root: to_hash(storage_root)?, code_hash: to_hash(code_hash)? })
+    }
+}
+
+// 账户trie加每个账户的存储trie, 账户的key为keccak(address), 存储的key为keccak(slot)
+pub struct StateDB {
+    reader: Rc<dyn NodeReader>,
+    state_root: Hash,
+    accounts: Trie,
+    // 打开过的存储trie, 以keccak(address)为key
+    storages: BTreeMap<Hash, Trie>,
+}
+
+impl StateDB {
+    pub fn new(root: Hash, reader: Rc<dyn NodeReader>) -> Result<Self, TrieError> {
+        let accounts = Trie::open(ID::state_trie_id(root), Rc::clone(&reader))?;
+        Ok(StateDB { reader, state_root: root, accounts, storages: BTreeMap::new() })
+    }
+
+    pub fn get_account(&self, address: &Address) -> Result<Option<StateAccount>, TrieError> {
+        match self.accounts.get(keccak256(address).as_slice())? {
+            Some(blob) => Ok(Some(StateAccount::decode(&blob)?)),
+            None => Ok(None),
+        }
+    }
+    // 更新账户的nonce、balance和codeHash, storageRoot由存储trie决定
+    pub fn update_account(&mut self, address: &Address, account: &StateAccount) -> Result<(), TrieError> {
+        let mut account = account.clone();
+        if let Some(prev) = self.get_account(address)? {
+            account.storage_root = prev.storage_root;
+        } else {
+            account.storage_root = Hash::empty_root_hash();
+        }
+        self.accounts.try_update(keccak256(address).to_vec(), Some(account.encode()))
+    }
+    pub fn delete_account(&mut self, address: &Address) -> Result<(), TrieError> {
+        let owner = keccak256(address);
+        self.storages.insert(owner, Trie::new(ID::storage_trie_id(self.state_root, owner, Hash::empty_root_hash())));
+        self.accounts.try_update(owner.to_vec(), None)
+    }
+
+    // 读取存储槽, 不存在时返回空
+    pub fn get_storage(&mut self, address: &Address, slot: &Hash) -> Result<Vec<u8>, TrieError> {
+        let trie = self.storage_trie(address)?;
+        match trie.get(keccak256(slot.as_slice()).as_slice())? {
+            Some(blob) => Ok(rlp::split_string(&blob)?.0.to_vec()),
+            None => Ok(Vec::new()),
+        }
+    }
+    // 写入存储槽, 值按大端去掉前导0后RLP编码保存, 值为0时删除
+    pub fn set_storage(&mut self, address: &Address, slot: &Hash, value: &[u8]) -> Result<(), TrieError> {
+        if self.get_account(address)?.is_none() {
+            self.update_account(address, &StateAccount::default())?;
+        }
+        let value = trim(value);
+        let trie = self.storage_trie(address)?;
+        let key = keccak256(slot.as_slice()).to_vec();
+        if value.is_empty() {
+            return trie.try_update(key, None);
+        }
+        let mut w = EncodeBuffer::new();
+        w.write_bytes(value);
+        trie.try_update(key, Some(w.encode_bytes()))
+    }
+
+    // 把存储trie的root写回账户, 返回当前的state root
+    pub fn intermediate_root(&mut self) -> Result<Hash, TrieError> {
+        let owners: Vec<Hash> = self.storages.keys().copied().collect();
+        for owner in owners {
+            let root = match self.storages.get_mut(&owner) {
+                Some(trie) => trie.hash()?,
+                None => continue,
+            };
+            let mut account = match self.accounts.get(owner.as_slice())? {
+                Some(blob) => StateAccount::decode(&blob)?,
+                None => continue,
+            };
+            if account.storage_root != root {
+                account.storage_root = root;
+                self.accounts.try_update(owner.to_vec(), Some(account.encode()))?;
+            }
+        }
+        self.accounts.hash()
+    }
+
+    // 提交所有修改, 返回新的state root和需要写入存储的节点, 存储trie的NodeSet在前
+    pub fn commit(&mut self) -> Result<(Hash, Vec<NodeSet>), TrieError> {
+        self.intermediate_root()?;
+        let mut sets = Vec::new();
+        for trie in self.storages.values_mut() {
+            let (_, set) = trie.commit()?;
+            if !set.is_empty() {
+                sets.push(set);
+            }
+        }
+        let (root, set) = self.accounts.commit()?;
+        sets.push(set);
+        self.state_root = root;
+        Ok((root, sets))
+    }
+
+    // 生成eth_getProof格式的账户和存储证明
+    pub fn get_proof(&mut self, address: &Address, slots: &[Hash]) -> Result<AccountProof, TrieError> {
+        self.intermediate_root()?;
+        let account = self.get_account(address)?.unwrap_or_default();
+        let account_proof = self.accounts.prove(keccak256(address).as_slice())?;
+        let mut storage_proof = Vec::with_capacity(slots.len());
+        for slot in slots {
+            let value = self.get_storage(address, slot)?;
+            let trie = self.storage_trie(address)?;
+            let proof = trie.prove(keccak256(slot.as_slice()).as_slice())?;
+            storage_proof.push(StorageProof { key: *slot, value, proof });
+        }
+        Ok(AccountProof {
+            address: *address,
+            balance: account.balance,
+            code_hash: account.code_hash,
+            nonce: account.nonce,
+            storage_hash: account.storage_root,
+            account_proof,
+            storage_proof,
+        })
+    }
+
+    fn storage_trie(&mut self, address: &Address) -> Result<&mut Trie, TrieError> {
+        let owner = keccak256(address);
+        if !self.storages.contains_key(&owner) {
+            let root = match self.get_account(address)? {
+                Some(account) => account.storage_root,
+                None => Hash::empty_root_hash(),
+            };
+            let trie = Trie::open(ID::storage_trie_id(self.state_root, owner, root), Rc::clone(&self.reader))?;
+            self.storages.insert(owner, trie);
+        }
+        match self.storages.get_mut(&owner) {
+            Some(trie) => Ok(trie),
+            None => Err(TrieError::invalid("storage trie not opened")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StorageProof {
+    pub key: Hash,
+    // 大端去掉前导0的值, 0为空
+    pub value: Vec<u8>,
+    pub proof: Vec<Vec<u8>>,
+}
+
+// EIP-1186 eth_getProof的返回结构
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountProof {
+    pub address: Address,
+    pub balance: U256,
+    pub code_hash: Hash,
+    pub nonce: u64,
+    pub storage_hash: Hash,
+    pub account_proof: Vec<Vec<u8>>,
+    pub storage_proof: Vec<StorageProof>,
+}
+
+impl AccountProof {
+    // 与RPC相同的JSON
+    pub fn to_json(&self) -> String {
+        let storage = self.storage_proof.iter().map(|p| Json::Obj(vec![
+            ("key".to_string(), Json::Str(to_hex(p.key.as_slice()))),
+            ("value".to_string(), Json::Str(to_quantity(&p.value))),
+            ("proof".to_string(), hex_list(&p.proof)),
+        ])).collect();
+        Json::Obj(vec![
+            ("address".to_string(), Json::Str(to_hex(&self.address))),
+            ("accountProof".to_string(), hex_list(&self.account_proof)),
+            ("balance".to_string(), Json::Str(to_quantity(&self.balance))),
+            ("codeHash".to_string(), Json::Str(to_hex(self.code_hash.as_slice()))),
+            ("nonce".to_string(), Json::Str(to_quantity(&self.nonce.to_be_bytes()))),
+            ("storageHash".to_string(), Json::Str(to_hex(self.storage_hash.as_slice()))),
+            ("storageProof".to_string(), Json::Arr(storage)),
+        ]).encode()
+    }
+
+    // 接受result对象本身或完整的JSON-RPC响应
+    pub fn from_json(s: &str) -> Result<Self, TrieError> {
+        let v = Json::parse(s)?;
+        if let Ok(err) = v.get("error") {
+            return Err(TrieError::DecodeError(format!("json-rpc error: {}", err.encode())));
+        }
+        let v = match v.get("result") {
+            Ok(result) => result.clone(),
+            Err(_) => v,
+        };
+        let address = from_hex(v.get("address")?.as_str()?)?;
+        let address: Address = match address.try_into() {
+            Ok(address) => address,
+            Err(_) => return Err(TrieError::DecodeError("address must be 20 bytes".to_string())),
+        };
+        let mut storage_proof = Vec::new();
+        for p in v.get("storageProof")?.as_arr()? {
+            let key = from_hex(p.get("key")?.as_str()?)?;
+            if key.len() > 32 {
+                return Err(TrieError::DecodeError("storage key longer than 32 bytes".to_string()));
+            }
+            // RPC中的key可能省略前导0
+            let mut slot = [0_u8; 32];
+            slot[32 - key.len()..].copy_from_slice(&key);
+            storage_proof.push(StorageProof {
+                key: Hash::from(slot),
+                value: trim(&from_hex(p.get("value")?.as_str()?)?).to_vec(),
+                proof: parse_hex_list(p.get("proof")?)?,
+            });
+        }
+        Ok(AccountProof {
+            address,
+            balance: be_u256(trim(&from_hex(v.get("balance")?.as_str()?)?))?,
+            code_hash: to_hash(&from_hex(v.get("codeHash")?.as_str()?)?)?,
+            nonce: be_u64(trim(&from_hex(v.get("nonce")?.as_str()?)?))?,
+            storage_hash: to_hash(&from_hex(v.get("storageHash")?.as_str()?)?)?,
+            account_proof: parse_hex_list(v.get("accountProof")?)?,
+            storage_proof,
+        })
+    }
+
+    // 对照state root验证账户字段和每个存储槽的值
+    pub fn verify(&self, state_root: Hash) -> Result<(), TrieError> {
+        let account = StateAccount { nonce: self.nonce, balance: self.balance, storage_root: self.storage_hash, code_hash: self.code_hash };
+        match verify_proof(state_root, keccak256(&self.address).as_slice(), &self.account_proof)? {
+            Some(blob) => {
+                if StateAccount::decode(&blob)? != account {
+                    return Err(TrieError::InvalidProof("account fields do not match proof".to_string()));
+                }
+            },
+            None => {
+                if account != StateAccount::default() {
+                    return Err(TrieError::InvalidProof("account does not exist".to_string()));
+                }
+            },
+        }
+        for p in self.storage_proof.iter() {
+            let value = match verify_proof(self.storage_hash, keccak256(p.key.as_slice()).as_slice(), &p.proof)? {
+                Some(blob) => rlp::split_string(&blob)?.0.to_vec(),
+                None => Vec::new(),
+            };
+            if value != trim(&p.value) {
+                return Err(TrieError::InvalidProof(format!("storage value mismatch for slot {}", p.key)));
+            }
+        }
+        Ok(())
+    }
+}
+
+// 解析eth_getProof的JSON并对照state root验证
+pub fn verify_account_proof(state_root: Hash, json: &str) -> Result<AccountProof, TrieError> {
+    let proof = AccountProof::from_json(json)?;
+    proof.verify(state_root)?;
+    Ok(proof)
+}
+
+fn hex_list(nodes: &[Vec<u8>]) -> Json {
+    Json::Arr(nodes.iter().map(|n| Json::Str(to_hex(n))).collect())
+}
+
+fn parse_hex_list(v: &Json) -> Result<Vec<Vec<u8>>, TrieError> {
+    v.as_arr()?.iter().map(|n| from_hex(n.as_str()?)).collect()
+}
+
+fn trim(data: &[u8]) -> &[u8] {
+    let skip = data.iter().take_while(|b| **b == 0).count();
+    &data[skip..]
+}
+
+fn be_u64(data: &[u8]) -> Result<u64, TrieError> {
+    if data.len() > 8 {
+        return Err(TrieError::DecodeError("integer overflows u64".to_string()));
+    }
+    Ok(data.iter().fold(0, |v, b| (v << 8) | *b as u64))
+}
+
+fn be_u256(data: &[u8]) -> Result<U256, TrieError> {
+    if data.len() > 32 {
+        return Err(TrieError::DecodeError("integer overflows u256".to_string()));
+    }
+    let mut v = [0_u8; 32];
+    v[32 - data.len()..].copy_from_slice(data);
+    Ok(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::MemoryDB;
+
+    fn address(i: u8) -> Address {
+        [i; 20]
+    }
+
+    fn slot(i: u8) -> Hash {
+        let mut s = [0_u8; 32];
+        s[31] = i;
+        Hash::from(s)
+    }
+
+    // 超过u128的余额
+    fn big_balance() -> U256 {
+        let mut b = [0_u8; 32];
+        b[0] = 0x01;
+        b[31] = 0x2a;
+        b
+    }
+
+    fn sample() -> (Hash, Rc<MemoryDB>) {
+        let db = Rc::new(MemoryDB::new());
+        let mut s = StateDB::new(Hash::empty_root_hash(), db.clone()).unwrap();
+        for i in 1..20u8 {
+            let mut balance = [0_u8; 32];
+            balance[31] = i;
+            s.update_account(&address(i), &StateAccount { nonce: i as u64, balance, ..Default::default() }).unwrap();
+            s.set_storage(&address(i), &slot(1), &[i, 0]).unwrap();
+        }
+        s.update_account(&address(7), &StateAccount { nonce: 7, balance: big_balance(), ..Default::default() }).unwrap();
+        let (root, sets) = s.commit().unwrap();
+        for set in sets.iter() {
+            db.update(set);
+        }
+        (root, db)
+    }
+
+    #[test]
+    fn account_encoding() {
+        let account = StateAccount { nonce: 1, balance: big_balance(), ..Default::default() };
+        assert_eq!(StateAccount::decode(&account.encode()).unwrap(), account);
+        // 余额按去掉前导0的大端编码
+        let small = StateAccount { balance: [0; 32], ..Default::default() };
+        assert_eq!(&small.encode()[..4], &[0xf8, 0x44, 0x80, 0x80]);
+        assert!(be_u256(&[1; 33]).is_err());
+    }
+
+    #[test]
+    fn state_persists() {
+        let (root, db) = sample();
+        let mut s = StateDB::new(root, db).unwrap();
+        assert_eq!(s.get_account(&address(7)).unwrap().unwrap().balance, big_balance());
+        assert_eq!(s.get_storage(&address(3), &slot(1)).unwrap(), vec![3, 0]);
+        assert_eq!(s.get_storage(&address(3), &slot(2)).unwrap(), Vec::<u8>::new());
+        s.set_storage(&address(3), &slot(1), &[0]).unwrap();
+        s.delete_account(&address(4)).unwrap();
+        assert_ne!(s.intermediate_root().unwrap(), root);
+        assert_eq!(s.get_account(&address(4)).unwrap(), None);
+    }
+
+    #[test]
+    fn proof_json_round_trip() {
+        let (root, db) = sample();
+        let mut s = StateDB::new(root, db).unwrap();
+        let proof = s.get_proof(&address(7), &[slot(1), slot(9)]).unwrap();
+        let json = proof.to_json();
+        assert!(json.contains("\"balance\":\"0x10000000000000000000000000000000000000000000000000000000000002a\""));
+        assert_eq!(verify_account_proof(root, &json).unwrap(), proof);
+        assert_eq!(proof.storage_proof[0].value, vec![7, 0]);
+        assert!(proof.storage_proof[1].value.is_empty());
+
+        // 完整的JSON-RPC响应
+        let envelope = format!("{{\"jsonrpc\":\"2.0\",\"id\":1,\"result\":{}}}", json);
+        assert_eq!(verify_account_proof(root, &envelope).unwrap(), proof);
+        let failed = "{\"jsonrpc\":\"2.0\",\"id\":1,\"error\":{\"code\":-32000,\"message\":\"missing trie node\"}}";
+        assert!(AccountProof::from_json(failed).is_err());
+
+        // 不存在的账户也能证明
+        let absent = s.get_proof(&address(99), &[slot(1)]).unwrap();
+        absent.verify(root).unwrap();
+
+        let mut forged = proof.clone();
+        forged.balance[31] ^= 1;
+        assert!(forged.verify(root).is_err());
+        let mut forged = proof;
+        forged.storage_proof[0].value = vec![8, 0];
+        assert!(forged.verify(root).is_err());
+    }
+}
@@ -0,0 +1,216 @@
+use std::{collections::BTreeMap, rc::Rc};
+
+use crate::{common::{keccak256, Hash}, database::NodeReader, node::{child_hashes, decode_node, HashNode}, nodeset::{NodeSet, TrieNode}, TrieError};
+
+// 正在下载的节点
+struct SyncRequest {
+    hash: Hash,
+    // 下载到的节点数据, 子节点未完成前暂存在这里
+    blob: Option<Vec<u8>>,
+    // 还未完成的子节点数
+    deps: usize,
+    // 父节点的路径, root没有父节点
+    parent: Option<Vec<u8>>,
+}
+
+// 按root下载整个trie: 给出本地缺少的节点, 接收下载的节点并校验, 再调度其未知的子节点
+// 只有子树全部完成的节点才会提交, 所以存储中的节点总是有完整的子树
+pub struct TrieSync {
+    owner: Hash,
+    reader: Rc<dyn NodeReader>,
+    // 以hex路径为key的未完成节点
+    requests: BTreeMap<Vec<u8>, SyncRequest>,
+    // 已完成等待提交的节点
+    done: NodeSet,
+    processed: usize,
+}
+
+impl TrieSync {
+    pub fn new(owner: Hash, root: Hash, reader: Rc<dyn NodeReader>) -> Result<Self, TrieError> {
+        let mut sync = TrieSync { owner, reader, requests: BTreeMap::new(), done: NodeSet::new(owner), processed: 0 };
+        if !root.is_empty_root() && !sync.has_node(&[], root)? {
+            sync.requests.insert(Vec::new(), SyncRequest { hash: root, blob: None, deps: 0, parent: None });
+        }
+        Ok(sync)
+    }
+
+    // 本地是否已有该节点, 数据与hash不符的节点视为缺失
+    fn has_node(&self, path: &[u8], hash: Hash) -> Result<bool, TrieError> {
+        match self.reader.node(self.owner, path, hash)? {
+            Some(blob) => Ok(keccak256(&blob) == hash),
+            None => Ok(false),
+        }
+    }
+
+    // 最多max个还未下载的节点(hex路径, hash), 路径更长的节点优先, 以便尽早完成子树
+    pub fn missing(&self, max: usize) -> Vec<(Vec<u8>, Hash)> {
+        let mut ret: Vec<(Vec<u8>, Hash)> = self.requests.iter()
+            .filter(|(_, req)| req.blob.is_none())
+            .map(|(path, req)| (path.clone(), req.hash))
+            .collect();
+        ret.sort_by(|a, b| b.0.len().cmp(&a.0.len()).then_with(|| a.0.cmp(&b.0)));
+        ret.truncate(max);
+        ret
+    }
+
+    // 处理下载到的节点数据, 返回新调度的子节点数, 数据的hash不是任何待下载节点时返回错误
+    pub fn process(&mut self, blob: Vec<u8>) -> Result<usize, TrieError> {
+        let hash = keccak256(&blob);
+        let paths: Vec<Vec<u8>> = self.requests.iter()
+            .filter(|(_, req)| req.hash == hash && req.blob.is_none())
+            .map(|(path, _)| path.clone())
+            .collect();
+        if paths.is_empty() {
+            return Err(TrieError::InvalidNode(format!("node {} was not requested", hash)));
+        }
+        // 先确认能解码, 不合法的数据不改变状态
+        decode_node(Some(HashNode::from(*hash)), &blob)?;
+        let children = child_hashes(&blob)?;
+        let mut scheduled = 0;
+        for path in paths {
+            let mut deps = 0;
+            for (rel, child) in children.iter() {
+                let mut child_path = path.clone();
+                child_path.extend(rel);
+                if self.requests.contains_key(&child_path) || self.has_node(&child_path, *child)? {
+                    continue;
+                }
+                self.requests.insert(child_path, SyncRequest { hash: *child, blob: None, deps: 0, parent: Some(path.clone()) });
+                deps += 1;
+            }
+            scheduled += deps;
+            if let Some(req) = self.requests.get_mut(&path) {
+                req.blob = Some(blob.clone());
+                req.deps = deps;
+            }
+            if deps == 0 {
+                self.complete(path);
+            }
+        }
+        self.processed += 1;
+        Ok(scheduled)
+    }
+
+    // 节点及其子树已完成, 移到待提交集合, 并检查父节点是否也完成了
+    fn complete(&mut self, mut path: Vec<u8>) {
+        while let Some(req) = self.requests.remove(&path) {
+            if let Some(blob) = req.blob {
+                self.done.add_node(path, TrieNode { hash: req.hash, blob });
+            }
+            let parent = match req.parent {
+                Some(parent) => parent,
+                None => return,
+            };
+            match self.requests.get_mut(&parent) {
+                Some(p) => {
+                    p.deps -= 1;
+                    if p.deps > 0 {
+                        return;
+                    }
+                },
+                None => return,
+            }
+            path = parent;
+        }
+    }
+
+    // 取出已完成的节点写入存储, 其中每个节点的子树都已在存储中或在同一批里
+    pub fn commit(&mut self) -> NodeSet {
+        std::mem::replace(&mut self.done, NodeSet::new(self.owner))
+    }
+
+    // 还未完成的节点数, 包括已下载但子树未完成的节点
+    pub fn pending(&self) -> usize {
+        self.requests.len()
+    }
+    // 已处理的节点数
+    pub fn processed(&self) -> usize {
+        self.processed
+    }
+    pub fn is_done(&self) -> bool {
+        self.requests.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::MemoryDB, Trie, ID};
+
+    // 用完整的MemoryDB充当远端节点
+    fn source(n: u32) -> (Hash, MemoryDB) {
+        let mut t = Trie::new(ID::trie_id(Hash::default()));
+        for i in 0..n {
+            t.try_update(keccak256(&i.to_be_bytes()).to_vec(), Some(vec![i as u8; 1 + (i % 50) as usize])).unwrap();
+        }
+        let (root, set) = t.commit().unwrap();
+        let db = MemoryDB::new();
+        db.update(&set);
+        (root, db)
+    }
+
+    fn serve(peer: &MemoryDB, reqs: &[(Vec<u8>, Hash)]) -> Vec<Vec<u8>> {
+        reqs.iter().map(|(path, hash)| peer.node(Hash::default(), path, *hash).unwrap().unwrap()).collect()
+    }
+
+    #[test]
+    fn sync_from_peer() {
+        let (root, peer) = source(2000);
+        let local = Rc::new(MemoryDB::new());
+        let mut sync = TrieSync::new(Hash::default(), root, local.clone()).unwrap();
+        let mut rounds = 0;
+        while !sync.is_done() {
+            let reqs = sync.missing(64);
+            assert!(!reqs.is_empty());
+            for blob in serve(&peer, &reqs) {
+                sync.process(blob).unwrap();
+            }
+            // 每批提交后存储中的节点子树都是完整的
+            local.update(&sync.commit());
+            rounds += 1;
+        }
+        assert!(rounds > 1);
+        assert_eq!(local.len(), peer.len());
+        let t = Trie::open(ID::trie_id(root), local).unwrap();
+        assert_eq!(t.iter().count(), 2000);
+    }
+
+    #[test]
+    fn resumes_from_partial_local_state() {
+        let (root, peer) = source(500);
+        let local = Rc::new(MemoryDB::new());
+        let mut sync = TrieSync::new(Hash::default(), root, local.clone()).unwrap();
+        for _ in 0..3 {
+            let reqs = sync.missing(16);
+            for blob in serve(&peer, &reqs) {
+                sync.process(blob).unwrap();
+            }
+            local.update(&sync.commit());
+        }
+        // 重新开始时已存储的子树不再下载
+        let mut sync = TrieSync::new(Hash::default(), root, local.clone()).unwrap();
+        let mut fetched = 0;
+        while !sync.is_done() {
+            let reqs = sync.missing(32);
+            fetched += reqs.len();
+            for blob in serve(&peer, &reqs) {
+                sync.process(blob).unwrap();
+            }
+        }
+        local.update(&sync.commit());
+        assert!(fetched < peer.len());
+        assert_eq!(local.len(), peer.len());
+        assert!(TrieSync::new(Hash::default(), root, local).unwrap().is_done());
+    }
+
+    #[test]
+    fn rejects_bad_data() {
+        let (root, peer) = source(100);
+        let mut sync = TrieSync::new(Hash::default(), root, Rc::new(MemoryDB::new())).unwrap();
+        assert!(sync.process(vec![0xc0]).is_err());
+        let mut blob = serve(&peer, &sync.missing(1)).remove(0);
+        blob[3] ^= 1;
+        assert!(sync.process(blob).is_err());
+        assert_eq!(sync.missing(1)[0].1, root);
+    }
+}
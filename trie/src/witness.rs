@@ -0,0 +1,174 @@
+use std::collections::{btree_map, BTreeMap};
+
+use crate::{common::{keccak256, Hash}, rlp, writer::EncodeBuffer, TrieError};
+
+// 执行过程中访问过的存储节点, 按hash去重, 用于无状态验证
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Witness {
+    nodes: BTreeMap<Hash, Vec<u8>>,
+}
+
+impl Witness {
+    pub fn new() -> Self {
+        Witness { nodes: BTreeMap::new() }
+    }
+    pub fn add(&mut self, blob: Vec<u8>) {
+        self.nodes.insert(keccak256(&blob), blob);
+    }
+    pub(crate) fn add_with_hash(&mut self, hash: Hash, blob: Vec<u8>) {
+        self.nodes.entry(hash).or_insert(blob);
+    }
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.nodes.contains_key(hash)
+    }
+    pub fn get(&self, hash: &Hash) -> Option<&Vec<u8>> {
+        self.nodes.get(hash)
+    }
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+    pub fn iter(&self) -> btree_map::Iter<'_, Hash, Vec<u8>> {
+        self.nodes.iter()
+    }
+    // 所有节点数据, 按hash排序
+    pub fn nodes(&self) -> Vec<Vec<u8>> {
+        self.nodes.values().cloned().collect()
+    }
+    // 合并另一个witness, 例如同一区块中多个trie的witness
+    pub fn merge(&mut self, other: &Witness) {
+        for (hash, blob) in other.iter() {
+            self.add_with_hash(*hash, blob.clone());
+        }
+    }
+
+    // 编码为节点数据的RLP列表
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = EncodeBuffer::new();
+        let offset = w.list();
+        for blob in self.nodes.values() {
+            w.write_bytes(blob);
+        }
+        w.list_end(offset);
+        w.encode_bytes()
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, TrieError> {
+        let (mut elems, _) = rlp::split_list(buf)?;
+        let mut witness = Witness::new();
+        while !elems.is_empty() {
+            let (blob, rest) = rlp::split_string(elems)?;
+            witness.add(blob.to_vec());
+            elems = rest;
+        }
+        Ok(witness)
+    }
+}
+
+impl From<Vec<Vec<u8>>> for Witness {
+    fn from(nodes: Vec<Vec<u8>>) -> Self {
+        let mut witness = Witness::new();
+        for blob in nodes {
+            witness.add(blob);
+        }
+        witness
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::{database::MemoryDB, Trie, ID};
+
+    // 删除后只剩一个已在内存中的兄弟节点时, 合并用到的兄弟节点也要记录
+    #[test]
+    fn delete_collapse_after_commit() {
+        let mut t = Trie::new(ID::trie_id(Hash::default()));
+        for k in [0x10u8, 0x20, 0x30] {
+            t.try_update(vec![k], Some(vec![k; 40])).unwrap();
+        }
+        let (r1, _) = t.commit().unwrap();
+        t.start_witness();
+        t.try_update(vec![0x20], None).unwrap();
+        t.try_update(vec![0x30], None).unwrap();
+        let w = t.stop_witness().unwrap();
+        let r2 = t.hash().unwrap();
+
+        let mut replay = Trie::from_witness(r1, &w.nodes()).unwrap();
+        replay.try_update(vec![0x20], None).unwrap();
+        replay.try_update(vec![0x30], None).unwrap();
+        assert_eq!(replay.hash().unwrap(), r2);
+    }
+
+    fn key(i: u32) -> Vec<u8> {
+        keccak256(&i.to_be_bytes()).to_vec()
+    }
+
+    fn stored(n: u32) -> (Hash, Rc<MemoryDB>) {
+        let mut t = Trie::new(ID::trie_id(Hash::default()));
+        for i in 0..n {
+            t.try_update(key(i), Some(vec![i as u8; 1 + (i % 40) as usize])).unwrap();
+        }
+        let (root, set) = t.commit().unwrap();
+        let db = Rc::new(MemoryDB::new());
+        db.update(&set);
+        (root, db)
+    }
+
+    // 执行区块时记录witness, 只用pre-root和witness重放写入得到相同的post-root
+    #[test]
+    fn stateless_replay() {
+        let (root, db) = stored(500);
+        let writes: Vec<(Vec<u8>, Option<Vec<u8>>)> = (0..40u32)
+            .map(|i| (key(i * 13), if i % 3 == 0 { None } else { Some(vec![0xab; 1 + i as usize]) }))
+            .chain(std::iter::once((key(9999), Some(vec![1, 2, 3]))))
+            .collect();
+        let mut t = Trie::open(ID::trie_id(root), db).unwrap();
+        t.start_witness();
+        for (k, v) in writes.iter() {
+            t.try_update(k.clone(), v.clone()).unwrap();
+        }
+        let post = t.hash().unwrap();
+        let w = t.stop_witness().unwrap();
+        assert!(w.len() < 500);
+
+        let w = Witness::decode(&w.encode()).unwrap();
+        let mut replay = Trie::from_witness(root, &w.nodes()).unwrap();
+        for (k, v) in writes.iter() {
+            replay.try_update(k.clone(), v.clone()).unwrap();
+        }
+        assert_eq!(replay.hash().unwrap(), post);
+    }
+
+    // 证明构建的部分trie, 访问证明之外的路径返回MissingNode
+    #[test]
+    fn partial_trie_from_proof() {
+        let (root, db) = stored(500);
+        let mut full = Trie::open(ID::trie_id(root), db).unwrap();
+        let proof = full.prove(&key(1)).unwrap();
+        let mut t = Trie::from_witness(root, &proof).unwrap();
+        assert_eq!(t.get(&key(1)).unwrap(), Some(vec![1; 2]));
+        let far = (2..500).find(|i| key(*i)[0] >> 4 != key(1)[0] >> 4).unwrap();
+        assert!(matches!(t.get(&key(far)), Err(TrieError::MissingNode { .. })));
+        assert!(matches!(t.try_update(key(far), Some(vec![1])), Err(TrieError::MissingNode { .. })));
+
+        t.try_update(key(1), Some(vec![7; 33])).unwrap();
+        full.try_update(key(1), Some(vec![7; 33])).unwrap();
+        assert_eq!(t.hash().unwrap(), full.hash().unwrap());
+        assert!(Trie::from_witness(keccak256(b"other"), &proof).is_err());
+    }
+
+    #[test]
+    fn merge_dedups() {
+        let a = Witness::from(vec![vec![1], vec![2]]);
+        let mut b = Witness::from(vec![vec![2], vec![3]]);
+        b.merge(&a);
+        assert_eq!(b.len(), 3);
+        assert!(b.contains(&keccak256(&[1])));
+        assert_eq!(Witness::decode(&b.encode()).unwrap(), b);
+    }
+}
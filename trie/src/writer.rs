@@ -1,4 +1,4 @@
-
+// RLP编码缓冲区
 pub struct EncodeBuffer {
     data_buf: Vec<u8>
 }
@@ -9,13 +9,36 @@ impl EncodeBuffer {
     }
 }
 
+impl Default for EncodeBuffer {
+    fn default() -> Self {
+        EncodeBuffer::new()
+    }
+}
+
 impl EncodeBuffer {
+    // 按RLP字符串编码写入
     pub fn write_bytes(&mut self, buf: &[u8]) {
+        if buf.len() == 1 && buf[0] < 0x80 {
+            self.data_buf.push(buf[0]);
+            return;
+        }
+        self.write_header(0x80, buf.len());
         self.data_buf.extend_from_slice(buf);
     }
+    // 写入单个原始字节
     pub fn write(&mut self, b: u8) {
         self.data_buf.push(b);
     }
+    // 开始一个列表, 返回的位置传给list_end
+    pub fn list(&self) -> usize {
+        self.data_buf.len()
+    }
+    // 结束列表, 在列表开始位置插入列表头
+    pub fn list_end(&mut self, start: usize) {
+        let payload = self.data_buf.split_off(start);
+        self.write_header(0xc0, payload.len());
+        self.data_buf.extend(payload);
+    }
     pub fn size(&mut self) -> usize {
         self.data_buf.len()
     }
@@ -25,15 +48,15 @@ impl EncodeBuffer {
     pub fn reset(&mut self) {
         self.data_buf = Vec::new();
     }
-}
-
-// impl Write for EncodeBuffer {
-//     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-//         self.data_buf.extend_from_slice(buf);
-//         Ok(buf.len())
-//     }
 
-//     fn flush(&mut self) -> std::io::Result<()> {
-//         todo!()
-//     }
-// }
\ No newline at end of file
+    fn write_header(&mut self, offset: u8, size: usize) {
+        if size < 56 {
+            self.data_buf.push(offset + size as u8);
+            return;
+        }
+        let be = size.to_be_bytes();
+        let skip = be.iter().take_while(|v| **v == 0).count();
+        self.data_buf.push(offset + 55 + (be.len() - skip) as u8);
+        self.data_buf.extend_from_slice(&be[skip..]);
+    }
+}